@@ -2,10 +2,14 @@
 extern crate criterion;
 
 use criterion::{BatchSize, Criterion, ParameterizedBenchmark};
-use kvs::{KVEngine, KVPair, KVStore, SledKVEngine};
+use kvs::{
+    BTreeKVEngine, Codec, KVClient, KVEngine, KVPair, KVRequest, KVServer, KVStore,
+    MemoryKVEngine, SledKVEngine,
+};
 use rand::distributions::Alphanumeric;
 use rand::prelude::*;
 use rand::{thread_rng, Rng};
+use slog::{Discard, Logger};
 use std::convert::TryInto;
 use std::iter;
 use tempfile::TempDir;
@@ -46,6 +50,20 @@ fn engine_write(c: &mut Criterion) {
             },
             BatchSize::SmallInput,
         )
+    })
+    .with_function("btree_set", |b, _| {
+        b.iter_batched(
+            || {
+                let temp_dir = TempDir::new().unwrap();
+                (BTreeKVEngine::open(temp_dir.path()).unwrap(), temp_dir)
+            },
+            |(store, _temp_dir)| {
+                for i in 1..(1 << 2) {
+                    store.set(format!("{}", i), "bench".to_string()).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
     });
     c.bench("kvs_write", bench);
 }
@@ -64,10 +82,49 @@ fn engine_read(c: &mut Criterion) {
         let temp_dir = TempDir::new().unwrap();
         let store = SledKVEngine::open(temp_dir.path()).unwrap();
         read_setup_and_bench(b, store);
+    })
+    .with_function("btree_read", |b, _| {
+        let temp_dir = TempDir::new().unwrap();
+        let store = BTreeKVEngine::open(temp_dir.path()).unwrap();
+        read_setup_and_bench(b, store);
     });
     c.bench("kvs_write", bench);
 }
 
+fn codec_round_trip(c: &mut Criterion) {
+    let log = Logger::root(Discard, slog::o!());
+    let handle = KVServer::new(MemoryKVEngine::new())
+        .spawn("127.0.0.1:0".to_owned(), &log)
+        .unwrap();
+    let addr = handle.addr().to_string();
+    let val: String = thread_rng().sample_iter(Alphanumeric).take(1000).collect();
+
+    let bench = ParameterizedBenchmark::new(
+        "round_trip",
+        move |b, &codec| {
+            b.iter(|| {
+                for i in 1..(1 << 4) {
+                    let req = KVRequest::Set {
+                        key: format!("{}", i),
+                        val: val.to_owned(),
+                    };
+                    let client = KVClient::new(addr.to_owned(), req).unwrap();
+                    client.prefer_codec(codec).connect().unwrap();
+
+                    let req = KVRequest::Get {
+                        key: format!("{}", i),
+                    };
+                    let client = KVClient::new(addr.to_owned(), req).unwrap();
+                    client.prefer_codec(codec).connect().unwrap();
+                }
+            })
+        },
+        vec![Codec::Json, Codec::MessagePack, Codec::Bincode],
+    );
+    c.bench("codec", bench);
+    handle.stop().unwrap();
+}
+
 fn read_setup_and_bench<E: KVEngine>(b: &mut criterion::Bencher<'_>, engine: E) {
     let mut vec_pair = generate_pair();
 
@@ -106,5 +163,5 @@ fn generate_pair() -> Vec<KVPair> {
     vec_pair
 }
 
-criterion_group!(bench, engine_write, engine_read);
+criterion_group!(bench, engine_write, engine_read, codec_round_trip);
 criterion_main!(bench);
@@ -0,0 +1,7 @@
+fn main() {
+    println!("cargo:rerun-if-changed=proto/kvs.proto");
+
+    // the generated code is only used by the grpc feature
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/kvs.proto").expect("unable to compile proto/kvs.proto");
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::error::{KVError, Result};
+
+/// Port used when an address names a host only.
+pub const DEFAULT_PORT: u16 = 4000;
+
+const UNIX_PREFIX: &str = "unix:";
+
+/// Address a server listens on or a client connects to, written as:
+///
+/// - `127.0.0.1:4000`, `[::1]:4000` or `localhost:4000`,
+/// - `127.0.0.1`, `::1`, `[::1]` or `localhost`, using `DEFAULT_PORT`,
+/// - `unix:/run/kvs.sock` for a Unix domain socket.
+///
+/// Host names are resolved when binding or connecting, see `resolve`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerAddr {
+    Tcp { host: String, port: u16 },
+    Unix(PathBuf),
+}
+
+impl ServerAddr {
+    /// Parses a comma-separated list of addresses, such as
+    /// `127.0.0.1:4000,[::1]:4000`.
+    pub fn parse_list(s: &str) -> Result<Vec<ServerAddr>> {
+        s.split(',').map(|addr| addr.trim().parse()).collect()
+    }
+
+    /// Socket addresses of a TCP address, a host name possibly resolving
+    /// to several of them.
+    pub fn resolve(&self) -> Result<Vec<SocketAddr>> {
+        match self {
+            ServerAddr::Tcp { host, port } => {
+                let addrs: Vec<_> = (host.as_str(), *port)
+                    .to_socket_addrs()
+                    .map_err(|e| invalid(format!("unable to resolve {}: {}", self, e)))?
+                    .collect();
+                if addrs.is_empty() {
+                    return Err(invalid(format!("{} resolves to no address", self)));
+                }
+                Ok(addrs)
+            }
+            ServerAddr::Unix(_) => Err(invalid(format!("{} is not a TCP address", self))),
+        }
+    }
+}
+
+impl FromStr for ServerAddr {
+    type Err = KVError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with(UNIX_PREFIX) {
+            let path = &s[UNIX_PREFIX.len()..];
+            if path.is_empty() {
+                return Err(invalid(format!("missing socket path in {}", s)));
+            }
+            return Ok(ServerAddr::Unix(PathBuf::from(path)));
+        }
+
+        let (host, port) = if s.starts_with('[') {
+            // [IPv6] or [IPv6]:PORT
+            let end = s.find(']').ok_or_else(|| invalid(format!("missing ] in {}", s)))?;
+            match &s[end + 1..] {
+                "" => (&s[1..end], None),
+                rest if rest.starts_with(':') => (&s[1..end], Some(&rest[1..])),
+                _ => return Err(invalid(format!("unexpected characters after ] in {}", s))),
+            }
+        } else {
+            match s.rfind(':') {
+                // a bare IPv6 address, which cannot carry a port
+                Some(_) if s.matches(':').count() > 1 => (s, None),
+                Some(i) => (&s[..i], Some(&s[i + 1..])),
+                None => (s, None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(invalid(format!("missing host in {}", s)));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| invalid(format!("invalid port {} in {}", port, s)))?,
+            None => DEFAULT_PORT,
+        };
+        Ok(ServerAddr::Tcp {
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for ServerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerAddr::Tcp { host, port } if host.contains(':') => {
+                write!(f, "[{}]:{}", host, port)
+            }
+            ServerAddr::Tcp { host, port } => write!(f, "{}:{}", host, port),
+            ServerAddr::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+fn invalid(message: String) -> KVError {
+    KVError::InvalidAddress(message)
+}
@@ -0,0 +1,141 @@
+#[macro_use]
+extern crate clap;
+
+use clap::App;
+use kvs::{open_engine, EngineMeta, KVEngine, KVError, KVPair, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Pairs read from an engine at once while migrating.
+const COPY_BATCH: usize = 1000;
+
+fn main() -> Result<()> {
+    let yaml = load_yaml!("cli-admin.yml");
+    let m = App::from_yaml(yaml).get_matches();
+
+    match m.subcommand() {
+        ("migrate", Some(sub_input)) => {
+            let from = sub_input.value_of("from").unwrap();
+            let to = sub_input.value_of("to").unwrap();
+            let dir = Path::new(sub_input.value_of("DIR").unwrap());
+            let keep_old = sub_input.is_present("keep-old");
+
+            if let Err(e) = migrate(from, to, dir, keep_old) {
+                eprintln!("migration failed: {}", e);
+                process::exit(1);
+            }
+        }
+        _ => process::exit(1),
+    }
+
+    Ok(())
+}
+
+/// Streams every live pair of `dir` into a staging directory opened with
+/// the `to` engine, verifies it, then swaps the staging directory in.
+///
+/// The swap is two renames, so `dir` briefly does not exist: a crash in
+/// between leaves the data in the `.migrate-old` and `.migrate-tmp`
+/// siblings, to be renamed back by hand.
+fn migrate(from: &str, to: &str, dir: &Path, keep_old: bool) -> Result<()> {
+    if from == to {
+        return Err(KVError::StringError("source and target engines are the same".to_owned()));
+    }
+    match EngineMeta::read(dir)? {
+        Some(meta) if meta.engine == from => {}
+        Some(meta) => {
+            return Err(KVError::WrongEngine {
+                expected: from.to_owned(),
+                found: meta.engine,
+            })
+        }
+        None => return Err(KVError::StringError(format!("no data found in {:?}", dir))),
+    }
+
+    let staging = sibling(dir, "migrate-tmp")?;
+    let old = sibling(dir, "migrate-old")?;
+    for path in &[&staging, &old] {
+        if path.exists() {
+            return Err(KVError::StringError(format!(
+                "{:?} exists, left over by a previous migration",
+                path
+            )));
+        }
+    }
+
+    let (count, checksum) = {
+        let source = open_engine(from, dir)?;
+        let target = open_engine(to, &staging)?;
+
+        let (count, checksum) = for_each_pair(&source, |pair| target.set(pair.key, pair.val))?;
+        let (target_count, target_checksum) = for_each_pair(&target, |_| Ok(()))?;
+        if (count, checksum) != (target_count, target_checksum) {
+            return Err(KVError::StringError(format!(
+                "verification failed: read {} keys ({:016x}), wrote {} keys ({:016x})",
+                count, checksum, target_count, target_checksum
+            )));
+        }
+        (count, checksum)
+    };
+
+    // both engines are closed here, swap the directories, which is not
+    // atomic, see above
+    fs::rename(dir, &old)?;
+    if let Err(e) = fs::rename(&staging, dir) {
+        fs::rename(&old, dir)?;
+        return Err(e.into());
+    }
+    if !keep_old {
+        fs::remove_dir_all(&old)?;
+    }
+
+    println!(
+        "migrated {} keys from {} to {} (checksum {:016x})",
+        count, from, to, checksum
+    );
+    Ok(())
+}
+
+/// Hands every live pair of `engine` to `f` in key order, `COPY_BATCH` of
+/// them in memory at a time, and returns their number and FNV-1a checksum
+/// over keys and values.
+fn for_each_pair<E, F>(engine: &E, mut f: F) -> Result<(usize, u64)>
+where
+    E: KVEngine,
+    F: FnMut(KVPair) -> Result<()>,
+{
+    let (mut count, mut checksum) = (0, FNV_OFFSET);
+    let mut after: Option<String> = None;
+    loop {
+        let pairs = engine.scan_from("", after.as_deref(), COPY_BATCH)?;
+        after = match pairs.last() {
+            Some(pair) => Some(pair.key.to_owned()),
+            None => break,
+        };
+        for pair in pairs {
+            checksum = pair
+                .key
+                .bytes()
+                .chain(Some(0))
+                .chain(pair.val.bytes())
+                .chain(Some(0))
+                .fold(checksum, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME));
+            count += 1;
+            f(pair)?;
+        }
+    }
+    Ok((count, checksum))
+}
+
+fn sibling(dir: &Path, suffix: &str) -> Result<PathBuf> {
+    let dir = dir.canonicalize()?;
+    let name = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| KVError::StringError(format!("invalid data directory {:?}", dir)))?;
+    Ok(dir.with_file_name(format!("{}.{}", name, suffix)))
+}
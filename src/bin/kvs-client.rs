@@ -1,27 +1,23 @@
 #[macro_use]
 extern crate clap;
-extern crate dotenv_codegen;
 
 use clap::App;
-// use std::env;
-use kvs::{KVClient, KVRequest, Result};
+use kvs::{ClientTls, KVClient, KVError, KVPair, KVRequest, Result, ServerAddr};
 
+use std::path::Path;
 use std::process;
 
-fn main() -> Result<()> {
-    /*println!("{}", dotenv!("PORT"));
-    let key = "PATH";
-    match env::var_os(key) {
-        Some(val) => println!("{}: {:?}", key, val),
-        None => println!("{} is not defined in the environment", key),
-    }*/
+// exit status of `get --strict` for a missing key, 1 being used for errors
+const KEY_NOT_FOUND_STATUS: i32 = 2;
 
+fn main() -> Result<()> {
     let yaml = load_yaml!("cli-client.yml");
     let m = App::from_yaml(yaml).get_matches();
 
     if let (cmd, Some(sub_input)) = m.subcommand() {
         let addr = addr_subcmd_arg(sub_input);
-        let key = sub_input.value_of("KEY").unwrap();
+        let tls = tls_args(sub_input)?;
+        let key = sub_input.value_of("KEY").unwrap_or_default();
         // println!("{:#?}", sub_input);
 
         match cmd {
@@ -30,11 +26,14 @@ fn main() -> Result<()> {
                     key: key.to_owned(),
                 };
 
-                let mut kv_client = KVClient::new(addr, req)?;
+                let mut kv_client = client(addr, tls, req)?;
                 if let Some(resp) = kv_client.connect()? {
                     println!("{}", resp);
                 } else {
                     println!("Key not found");
+                    if sub_input.is_present("strict") {
+                        process::exit(KEY_NOT_FOUND_STATUS);
+                    }
                 }
             }
             "set" => {
@@ -43,20 +42,40 @@ fn main() -> Result<()> {
                     key: key.to_owned(),
                     val: val.to_owned(),
                 };
-                let mut kv_client = KVClient::new(addr, req)?;
+                let mut kv_client = client(addr, tls, req)?;
                 kv_client.connect()?;
             }
             "rm" => {
                 let req = KVRequest::Rm {
                     key: key.to_owned(),
                 };
-                let mut kv_client = KVClient::new(addr, req)?;
+                let mut kv_client = client(addr, tls, req)?;
                 match kv_client.connect() {
                     Ok(_) => {}
-                    Err(_) => {
+                    Err(KVError::NotFound(_)) => {
                         eprintln!("Key not found");
                         process::exit(1)
                     }
+                    Err(e) => return Err(e),
+                }
+            }
+            "scan" => {
+                let prefix = sub_input.value_of("PREFIX").unwrap_or_default();
+                let req = KVRequest::Scan {
+                    prefix: prefix.to_owned(),
+                };
+                let mut kv_client = client(addr, tls, req)?;
+                if let Some(resp) = kv_client.connect()? {
+                    let pairs: Vec<KVPair> = serde_json::from_str(&resp)?;
+                    for pair in pairs {
+                        println!("{} {}", pair.key, pair.val);
+                    }
+                }
+            }
+            "stats" => {
+                let mut kv_client = client(addr, tls, KVRequest::Stats)?;
+                if let Some(resp) = kv_client.connect()? {
+                    println!("{}", resp);
                 }
             }
             _ => process::exit(1),
@@ -68,19 +87,40 @@ fn main() -> Result<()> {
     }
 }
 
+/// Address given with `--addr`, in any form `ServerAddr` accepts.
 fn addr_subcmd_arg(sub_input: &clap::ArgMatches) -> String {
-    let mut ip_addr = "";
-    let mut port = "";
-    if let Some(arg) = sub_input.value_of("addr") {
-        let split_vec: Vec<&str> = arg.split(':').collect();
-        if split_vec.len() != 2 {
+    let addr = sub_input.value_of("addr").unwrap_or_default();
+    match addr.parse::<ServerAddr>() {
+        Ok(addr) => addr.to_string(),
+        Err(e) => {
+            eprintln!("{}", e);
             process::exit(1);
         }
-        ip_addr = split_vec[0];
-        port = split_vec[1];
     }
-    let tcp_addr = format!("{}:{}", ip_addr, port);
-    // println!("IPAdress: {}", tcpAddr);
+}
 
-    tcp_addr
+/// TLS settings given with `--tls-ca` or `--insecure`, and `--tls-cert`,
+/// which is refused without either of them rather than sent in the clear.
+fn tls_args(sub_input: &clap::ArgMatches) -> Result<Option<ClientTls>> {
+    let tls = match sub_input.value_of("tls-ca") {
+        Some(ca) => ClientTls::from_ca(Path::new(ca))?,
+        None if sub_input.is_present("insecure") => ClientTls::insecure(),
+        None if sub_input.is_present("tls-cert") => {
+            return Err(KVError::InvalidConfig(
+                "--tls-cert needs --tls-ca or --insecure".to_owned(),
+            ));
+        }
+        None => return Ok(None),
+    };
+    match (sub_input.value_of("tls-cert"), sub_input.value_of("tls-key")) {
+        (Some(cert), Some(key)) => Ok(Some(tls.identity(Path::new(cert), Path::new(key))?)),
+        _ => Ok(Some(tls)),
+    }
+}
+
+fn client(addr: String, tls: Option<ClientTls>, req: KVRequest) -> Result<KVClient> {
+    match tls {
+        Some(tls) => KVClient::with_tls(addr, tls, req),
+        None => KVClient::new(addr, req),
+    }
 }
@@ -6,93 +6,380 @@ extern crate slog;
 extern crate slog_async;
 extern crate slog_term;
 
-use clap::App;
-use kvs::{KVServer, KVStore, Result, SledKVEngine};
-use slog::{Drain, Logger};
+use clap::{App, ArgMatches};
+use kvs::{
+    build_stack, BTreeKVEngine, EngineMeta, KVEngine, KVError, KVServer, KVStore, LayerConfig,
+    LsmKVEngine, MemoryKVEngine, Result, ServerAddr, ServerConfig, ServerTls, SledKVEngine,
+};
+use slog::{Drain, LevelFilter, Logger};
+use std::env;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
-// use hello_web_server::ThreadPool;
+const DEFAULT_ENGINE: &str = "kvs";
 
 enum Engine {
     Kvs,
     Sled,
+    Lsm,
+    BTree,
+    Memory,
 }
 
 fn main() -> Result<()> {
+    let yaml = load_yaml!("cli-server.yml");
+    let m = App::from_yaml(yaml).get_matches();
+
+    let config = match load_config(&m) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let level = config.log_level.parse().unwrap_or(slog::Level::Info);
     let decorator = slog_term::PlainDecorator::new(std::io::stdout());
     let drain = slog_term::CompactFormat::new(decorator).build().fuse();
-    let drain = slog_async::Async::new(drain).build().fuse();
+    // the guard flushes pending records when main returns
+    let (drain, _log_guard) = slog_async::Async::new(drain).build_with_guard();
+    let drain = LevelFilter::new(drain.fuse(), level).fuse();
     let log = slog::Logger::root(drain, o!());
 
     info!(log, "kvs-server {}", env!("CARGO_PKG_VERSION"));
 
-    let yaml = load_yaml!("cli-server.yml");
-    let m = App::from_yaml(yaml).get_matches();
-
-    let mut ip_addr = "";
-    let mut port = "";
-    let mut engine = "";
-    if let Some(arg) = m.value_of("addr") {
-        let split_vec: Vec<&str> = arg.split(':').collect();
-        if split_vec.len() != 2 {
+    let dump_interval = match value_t!(m, "dump-interval", u64) {
+        Ok(secs) => secs,
+        Err(e) => {
+            eprintln!("{}", e);
             process::exit(1);
         }
-        ip_addr = split_vec[0];
-        port = split_vec[1];
-    }
+    };
 
-    if let Some(arg) = m.value_of("engine") {
-        if arg != "kvs" && arg != "sled" {
-            process::exit(1);
+    let mut layers = Vec::new();
+    for layer in m.values_of("layer").into_iter().flatten() {
+        match layer.parse::<LayerConfig>() {
+            Ok(layer) => layers.push(layer),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
         }
-        engine = arg;
+    }
+    if config.cache_size > 0 {
+        layers.push(LayerConfig::Cache(config.cache_size));
     }
 
-    let tcp_addr = format!("{}:{}", ip_addr, port);
-    // info!(log, "Storage engine: {}", engine);
-    // info!(log, "Listening on port: {}", tcpAddr);
+    let data_dir = match &config.data_dir {
+        Some(dir) => dir.to_owned(),
+        None => std::env::current_dir()?,
+    };
+
+    let read_only = m.is_present("read-only");
 
     eprintln!("kvs-server {}", env!("CARGO_PKG_VERSION"));
-    eprintln!("addr {}", tcp_addr);
+    eprintln!("addr {}", config.addr);
 
-    start_server(tcp_addr, engine.to_owned(), &log)?;
+    start_server(&config, &data_dir, &layers, dump_interval, read_only, &log)?;
 
     Ok(())
 }
 
-fn start_server(addr: String, engine: String, log: &Logger) -> Result<()> {
-    let current_path = std::env::current_dir()?;
+/// Builds the configuration from, by increasing priority: defaults, the
+/// `--config` file, `KVS_*` environment variables and command line flags.
+fn load_config(m: &ArgMatches) -> Result<ServerConfig> {
+    let mut config = match m.value_of("config") {
+        Some(path) => ServerConfig::from_file(Path::new(path))?,
+        None => ServerConfig::default(),
+    };
+
+    // variables that are not valid unicode cannot be ours
+    let vars = env::vars_os()
+        .filter_map(|(name, val)| Some((name.into_string().ok()?, val.into_string().ok()?)));
+    config.apply_env(vars)?;
+
+    if let Some(addr) = m.value_of("addr") {
+        config.addr = addr.to_owned();
+    }
+    if let Some(mode) = m.value_of("socket-mode") {
+        config.socket_mode = Some(u32::from_str_radix(mode, 8).map_err(|_| {
+            KVError::InvalidConfig(format!("invalid socket mode {}", mode))
+        })?);
+    }
+    if let Some(protocol) = m.value_of("protocol") {
+        config.protocol = protocol.parse()?;
+    }
+    if let Some(addr) = m.value_of("http-addr") {
+        config.http_addr = Some(addr.to_owned());
+    }
+    if let Some(path) = m.value_of("tls-cert") {
+        config.tls_cert = Some(PathBuf::from(path));
+    }
+    if let Some(path) = m.value_of("tls-key") {
+        config.tls_key = Some(PathBuf::from(path));
+    }
+    if let Some(path) = m.value_of("tls-client-ca") {
+        config.tls_client_ca = Some(PathBuf::from(path));
+    }
+    if let Some(addr) = m.value_of("grpc-addr") {
+        config.grpc_addr = Some(addr.to_owned());
+    }
+    if let Some(engine) = m.value_of("engine") {
+        config.engine = Some(engine.to_owned());
+    }
+    if let Some(dir) = m.value_of("data-dir") {
+        config.data_dir = Some(PathBuf::from(dir));
+    }
+    if m.is_present("cache-size") {
+        config.cache_size =
+            value_t!(m, "cache-size", usize).map_err(|e| KVError::InvalidConfig(e.message))?;
+    }
+    if m.is_present("threads") {
+        config.threads =
+            value_t!(m, "threads", usize).map_err(|e| KVError::InvalidConfig(e.message))?;
+    }
+    if let Some(level) = m.value_of("log-level") {
+        config.log_level = level.to_owned();
+    }
+
+    config.validate()?;
+    Ok(config)
+}
+
+fn start_server(
+    config: &ServerConfig,
+    data_dir: &Path,
+    layers: &[LayerConfig],
+    dump_interval: u64,
+    read_only: bool,
+    log: &Logger,
+) -> Result<()> {
+    info!(log, "Data directory: {}", data_dir.display());
+    let engine = config.engine.to_owned();
+
+    if read_only {
+        let engine = resolve_engine(engine, data_dir, log)?;
+        info!(log, "Storage engine: {} (read-only)", engine);
+        return match current_engine(engine, log) {
+            Some(Engine::Kvs) => {
+                let engine = KVStore::open_read_only(data_dir)?;
+                run_read_only(engine, config, layers, log)
+            }
+            Some(Engine::Sled) => {
+                let engine = SledKVEngine::open_read_only(data_dir)?;
+                run_read_only(engine, config, layers, log)
+            }
+            Some(_) => Err(KVError::StringError(
+                "read-only mode is only supported by the kvs and sled engines".to_owned(),
+            )),
+            None => Ok(()),
+        };
+    }
+
+    let uses_dir = engine.as_deref() != Some("memory") || dump_interval > 0;
+    let engine = if uses_dir {
+        resolve_engine(engine, data_dir, log)?
+    } else {
+        "memory".to_owned()
+    };
+    info!(log, "Storage engine: {}", engine);
     let engine = current_engine(engine, log);
+    let options = config.engine_options();
+    // also found here when detected from the data directory
+    if let (Some(Engine::Memory), true) = (&engine, options.sync) {
+        return Err(KVError::InvalidConfig("the memory engine does not sync writes".to_owned()));
+    }
 
     match engine {
         Some(Engine::Kvs) => {
-            let mut server = KVServer::new(KVStore::open(&current_path)?);
-            server.run(addr, log)?;
+            run_engine(KVStore::open_with(data_dir, &options)?, config, layers, log)?
+        }
+        Some(Engine::Sled) => run_engine(SledKVEngine::open(data_dir)?, config, layers, log)?,
+        Some(Engine::Lsm) => {
+            run_engine(LsmKVEngine::open_with(data_dir, &options)?, config, layers, log)?
         }
-        Some(Engine::Sled) => {
-            let mut server = KVServer::new(SledKVEngine::open(&current_path)?);
-            server.run(addr, log)?;
+        Some(Engine::BTree) => {
+            run_engine(BTreeKVEngine::open_with(data_dir, &options)?, config, layers, log)?
         }
+        Some(Engine::Memory) if dump_interval > 0 => {
+            let interval = Duration::from_secs(dump_interval);
+            let engine = MemoryKVEngine::open_with_dump(data_dir, interval)?;
+            run_engine(engine, config, layers, log)?
+        }
+        Some(Engine::Memory) => run_engine(MemoryKVEngine::new(), config, layers, log)?,
         None => {}
     }
 
     Ok(())
+}
+
+fn run_engine<E>(
+    engine: E,
+    config: &ServerConfig,
+    layers: &[LayerConfig],
+    log: &Logger,
+) -> Result<()>
+where
+    E: KVEngine + Send + 'static,
+{
+    if !layers.is_empty() {
+        info!(log, "Engine layers: {:?}", layers);
+        let server = KVServer::new(build_stack(engine, layers));
+        serve(server, config, log)
+    } else {
+        serve(KVServer::new(engine), config, log)
+    }
+}
+
+fn run_read_only<E>(
+    engine: E,
+    config: &ServerConfig,
+    layers: &[LayerConfig],
+    log: &Logger,
+) -> Result<()>
+where
+    E: KVEngine + Send + 'static,
+{
+    serve(KVServer::read_only(build_stack(engine, layers)), config, log)
+}
+
+/// Runs `server`, and its HTTP gateway and gRPC service if any, until SIGINT
+/// or SIGTERM is received.
+fn serve<E>(server: KVServer<E>, config: &ServerConfig, log: &Logger) -> Result<()>
+where
+    E: KVEngine + Send + 'static,
+{
+    info!(log, "Protocol: {:?}", config.protocol);
+    let mut server = server.protocol(config.protocol).threads(config.threads);
+    if let Some(mode) = config.socket_mode {
+        server = server.socket_mode(mode);
+    }
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) {
+        let tls = match &config.tls_client_ca {
+            Some(ca) => ServerTls::with_client_auth(cert, key, ca)?,
+            None => ServerTls::from_pem(cert, key)?,
+        };
+        info!(log, "TLS enabled, client certificates required: {}", config.tls_client_ca.is_some());
+        server = server.tls(tls);
+    }
+    let handle = server.shutdown_handle();
+    let signal_handle = handle.clone();
+    let signal_log = log.clone();
+    ctrlc::set_handler(move || {
+        info!(signal_log, "Shutdown requested");
+        signal_handle.shutdown();
+    })
+    .map_err(|e| KVError::StringError(e.to_string()))?;
+
+    let gateway = match &config.http_addr {
+        Some(addr) => {
+            let gateway = server.http_gateway(addr)?;
+            info!(log, "HTTP gateway: {}", gateway.local_addr());
+            let log = log.clone();
+            Some(thread::spawn(move || gateway.run(&log)))
+        }
+        None => None,
+    };
+    let grpc = match &config.grpc_addr {
+        Some(addr) => Some(spawn_grpc(&server, addr, log)?),
+        None => None,
+    };
 
-    /*let pool = ThreadPool::new(4);
+    let served = server.run(config.addr.to_owned(), log);
+    // the gateway stops with the server, even when it failed to start
+    handle.shutdown();
+    if let Some(gateway) = gateway {
+        gateway
+            .join()
+            .unwrap_or_else(|_| Err(KVError::StringError("HTTP gateway panicked".to_owned())))?;
+    }
+    if let Some(grpc) = grpc {
+        grpc.join()
+            .unwrap_or_else(|_| Err(KVError::StringError("gRPC service panicked".to_owned())))?;
+    }
+    served
+}
 
-    for stream in listener.incoming().take(2) {
-        let stream = stream?;
+/// Serves the gRPC service from a tokio runtime of its own, on every
+/// address `addr` resolves to.
+#[cfg(feature = "grpc")]
+fn spawn_grpc<E>(server: &KVServer<E>, addr: &str, log: &Logger) -> Result<JoinHandle<Result<()>>>
+where
+    E: KVEngine + Send + 'static,
+{
+    let mut runtime = tokio::runtime::Runtime::new()?;
+    let mut listeners = Vec::new();
+    for addr in addr.parse::<ServerAddr>()?.resolve()? {
+        // the error names the address, as a host may have several
+        let listener = runtime
+            .block_on(tokio::net::TcpListener::bind(addr))
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", addr, e)))?;
+        listeners.push(listener);
+    }
 
-        pool.execute(|| {
-            handle_connection(stream);
-        });
-    }*/
+    let server = server.clone();
+    let log = log.clone();
+    Ok(thread::spawn(move || {
+        runtime.block_on(async move {
+            let services: Vec<_> = listeners
+                .into_iter()
+                .map(|listener| {
+                    let (server, log) = (server.clone(), log.clone());
+                    tokio::spawn(async move { server.run_grpc(listener, &log).await })
+                })
+                .collect();
+            let mut served = Ok(());
+            for service in services {
+                let result = service.await.unwrap_or_else(|_| {
+                    Err(KVError::StringError("gRPC service panicked".to_owned()))
+                });
+                served = served.and(result);
+            }
+            served
+        })
+    }))
+}
+
+#[cfg(not(feature = "grpc"))]
+fn spawn_grpc<E>(_: &KVServer<E>, _: &str, _: &Logger) -> Result<JoinHandle<Result<()>>>
+where
+    E: KVEngine + Send + 'static,
+{
+    // refused by ServerConfig::validate
+    unreachable!("gRPC support was not compiled in")
+}
+
+/// Picks the engine owning `dir_path` when none is requested, and refuses
+/// a requested engine conflicting with the one found on disk.
+fn resolve_engine(requested: Option<String>, dir_path: &Path, log: &Logger) -> Result<String> {
+    let on_disk = EngineMeta::read(dir_path)?.map(|meta| meta.engine);
+
+    match (requested, on_disk) {
+        (Some(requested), Some(found)) if requested != found => {
+            error!(log, "Data directory belongs to {} engine, not {}", found, requested);
+            Err(KVError::WrongEngine {
+                expected: requested,
+                found,
+            })
+        }
+        (Some(requested), _) => Ok(requested),
+        (None, Some(found)) => {
+            info!(log, "Detected {} engine from data directory", found);
+            Ok(found)
+        }
+        (None, None) => Ok(DEFAULT_ENGINE.to_owned()),
+    }
 }
 
 fn current_engine(engine: String, log: &Logger) -> Option<Engine> {
     match engine.as_ref() {
         "kvs" => Some(Engine::Kvs),
         "sled" => Some(Engine::Sled),
+        "lsm" => Some(Engine::Lsm),
+        "btree" => Some(Engine::BTree),
+        "memory" => Some(Engine::Memory),
         _ => {
             warn!(log, "Error -> engine {} found not found", engine);
             None
@@ -1,45 +1,141 @@
 use crate::error::{KVError, Result};
+use crate::protocol::{check_negotiated, Capability, Codec, CAPABILITIES, PROTOCOL_VERSION};
+use crate::tls::ClientTls;
+use crate::transport::Stream;
 use crate::{KVRequest, KVResponse};
-use serde::Deserialize;
-use serde_json::Deserializer;
-use std::io::{BufReader, BufWriter, Write};
-use std::net::TcpStream;
+use std::io::{BufReader, BufWriter};
+
+#[cfg(feature = "async")]
+pub use self::async_client::AsyncKVClient;
+
+#[cfg(feature = "async")]
+mod async_client;
 
 pub struct KVClient {
+    addr: String,
+    tls: Option<ClientTls>,
     req: KVRequest,
-    writer: BufWriter<TcpStream>,
-    reader: BufReader<TcpStream>,
+    writer: BufWriter<Stream>,
+    reader: BufReader<Stream>,
+    version: u32,
+    capabilities: Vec<Capability>,
+    preferred: Codec,
+    codec: Codec,
 }
 
 impl KVClient {
+    /// Connects to `addr`, either `IP:PORT` or `unix:PATH`.
     pub fn new(addr: String, req: KVRequest) -> Result<Self> {
-        let stream_w = TcpStream::connect(&addr)?;
-        let stream_r = stream_w.try_clone()?;
-        let writer = BufWriter::new(stream_w);
-        let reader = BufReader::new(stream_r);
+        KVClient::open_with(addr, None, req)
+    }
+
+    /// Connects to `addr` over TLS, for servers started with
+    /// `KVServer::tls`.
+    pub fn with_tls(addr: String, tls: ClientTls, req: KVRequest) -> Result<Self> {
+        KVClient::open_with(addr, Some(tls), req)
+    }
+
+    fn open_with(addr: String, tls: Option<ClientTls>, req: KVRequest) -> Result<Self> {
+        let (writer, reader) = open(&addr, tls.as_ref())?;
 
-        // println!("request: {:#?}", req);
         Ok(KVClient {
+            addr,
+            tls,
             req,
             writer,
             reader,
+            version: PROTOCOL_VERSION,
+            capabilities: Vec::new(),
+            preferred: Codec::Json,
+            codec: Codec::Json,
         })
     }
 
+    /// Asks the server to encode the request and its answer with `codec`,
+    /// JSON being used when the server does not speak it.
+    pub fn prefer_codec(mut self, codec: Codec) -> Self {
+        self.preferred = codec;
+        self
+    }
+
+    /// Sends the request after the handshake, falling back to the bare
+    /// request for servers predating protocol versioning.
     pub fn connect(&mut self) -> Result<Option<String>> {
-        let mut buf = vec![];
-        serde_json::to_writer(&mut buf, &self.req)?;
-        let _len = buf.len() as u64;
+        match self.handshake() {
+            Ok(()) => {}
+            // such servers reject the `Hello` and close the connection
+            Err(KVError::Protocol(_)) => {
+                let (writer, reader) = open(&self.addr, self.tls.as_ref())?;
+                self.writer = writer;
+                self.reader = reader;
+                self.version = 0;
+                self.capabilities.clear();
+                self.codec = Codec::Json;
+            }
+            Err(e) => return Err(e),
+        }
+
+        self.codec.write(&mut self.writer, &self.req)?;
+        match self.codec.read(&mut self.reader)? {
+            KVResponse::Ok(value) => Ok(value),
+            KVResponse::Err { code, message } => Err(KVError::from_response(code, message)),
+            KVResponse::Hello { .. } => Err(KVError::Protocol("unexpected handshake".to_owned())),
+        }
+    }
+
+    /// Protocol version agreed on with the server, 0 for servers predating
+    /// versioning.
+    pub fn protocol_version(&self) -> u32 {
+        self.version
+    }
 
-        self.writer.write_all(&buf)?;
-        self.writer.flush()?;
+    /// Capabilities supported by both the client and the server.
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
 
-        let mut deserializer = Deserializer::from_reader(&mut self.reader);
+    /// Codec the request was sent with.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
 
-        // let mut resp = KVResponse::deserialize(&mut deserializer)?;
-        match KVResponse::deserialize(&mut deserializer)? {
-            KVResponse::Ok(value) => Ok(value),
-            KVResponse::Err(msg) => Err(KVError::StringError(msg)),
+    fn handshake(&mut self) -> Result<()> {
+        let hello = KVRequest::Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.to_vec(),
+            codecs: offered_codecs(self.preferred),
+        };
+        Codec::Json.write(&mut self.writer, &hello)?;
+
+        match Codec::Json.read(&mut self.reader)? {
+            KVResponse::Hello {
+                version,
+                capabilities,
+                codec,
+            } => {
+                check_negotiated(version)?;
+                self.version = version;
+                self.capabilities = capabilities;
+                self.codec = codec;
+                Ok(())
+            }
+            KVResponse::Err { code, message } => Err(KVError::from_response(code, message)),
+            KVResponse::Ok(_) => Err(KVError::Protocol("unexpected handshake answer".to_owned())),
         }
     }
 }
+
+fn open(addr: &str, tls: Option<&ClientTls>) -> Result<(BufWriter<Stream>, BufReader<Stream>)> {
+    let stream_w = Stream::connect(addr, tls)?;
+    let stream_r = stream_w.try_clone()?;
+    Ok((BufWriter::new(stream_w), BufReader::new(stream_r)))
+}
+
+/// Codecs offered in a `Hello`, falling back to JSON.
+fn offered_codecs(preferred: Codec) -> Vec<Codec> {
+    let mut codecs = vec![preferred];
+    if preferred != Codec::Json {
+        codecs.push(Codec::Json);
+    }
+    codecs
+}
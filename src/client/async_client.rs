@@ -0,0 +1,100 @@
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use super::offered_codecs;
+use crate::addr::ServerAddr;
+use crate::common_struct::{KVRequest, KVResponse};
+use crate::error::{KVError, Result};
+use crate::protocol::{check_negotiated, read_message, Codec, CAPABILITIES, PROTOCOL_VERSION};
+
+/// Tokio flavour of `KVClient`, opening one connection per request like
+/// the blocking client.
+#[derive(Clone)]
+pub struct AsyncKVClient {
+    addr: String,
+    preferred: Codec,
+}
+
+impl AsyncKVClient {
+    pub fn new(addr: String) -> Self {
+        AsyncKVClient {
+            addr,
+            preferred: Codec::Json,
+        }
+    }
+
+    /// Asks the server to encode requests and their answers with `codec`,
+    /// JSON being used when the server does not speak it.
+    pub fn prefer_codec(mut self, codec: Codec) -> Self {
+        self.preferred = codec;
+        self
+    }
+
+    pub async fn get(&self, key: String) -> Result<Option<String>> {
+        self.request(&KVRequest::Get { key }).await
+    }
+
+    pub async fn set(&self, key: String, val: String) -> Result<()> {
+        self.request(&KVRequest::Set { key, val }).await?;
+        Ok(())
+    }
+
+    pub async fn remove(&self, key: String) -> Result<()> {
+        self.request(&KVRequest::Rm { key }).await?;
+        Ok(())
+    }
+
+    /// Sends `req` after the handshake, falling back to the bare request
+    /// for servers predating protocol versioning.
+    pub async fn request(&self, req: &KVRequest) -> Result<Option<String>> {
+        let mut stream = connect(&self.addr).await?;
+        let codec = match handshake(&mut stream, self.preferred).await {
+            Ok(codec) => codec,
+            // such servers reject the `Hello` and close the connection
+            Err(KVError::Protocol(_)) => {
+                stream = connect(&self.addr).await?;
+                Codec::Json
+            }
+            Err(e) => return Err(e),
+        };
+
+        stream.write_all(&codec.encode(req)?).await?;
+        match read_message(&mut stream, codec).await? {
+            KVResponse::Ok(value) => Ok(value),
+            KVResponse::Err { code, message } => Err(KVError::from_response(code, message)),
+            KVResponse::Hello { .. } => Err(KVError::Protocol("unexpected handshake".to_owned())),
+        }
+    }
+}
+
+/// Connects to `addr`, trying each address a host name resolves to.
+async fn connect(addr: &str) -> Result<TcpStream> {
+    let mut last_error = None;
+    for socket_addr in addr.parse::<ServerAddr>()?.resolve()? {
+        match TcpStream::connect(socket_addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    // `resolve` never answers an empty list
+    Err(last_error.unwrap().into())
+}
+
+/// Codec agreed on with the server.
+async fn handshake(stream: &mut TcpStream, preferred: Codec) -> Result<Codec> {
+    let hello = KVRequest::Hello {
+        version: PROTOCOL_VERSION,
+        capabilities: CAPABILITIES.to_vec(),
+        codecs: offered_codecs(preferred),
+    };
+    stream.write_all(&Codec::Json.encode(&hello)?).await?;
+
+    match read_message(stream, Codec::Json).await? {
+        KVResponse::Hello { version, codec, .. } => {
+            check_negotiated(version)?;
+            Ok(codec)
+        }
+        KVResponse::Err { code, message } => Err(KVError::from_response(code, message)),
+        KVResponse::Ok(_) => Err(KVError::Protocol("unexpected handshake answer".to_owned())),
+    }
+}
@@ -1,16 +1,60 @@
 use serde::{Deserialize, Serialize};
 
+use crate::protocol::{Capability, Codec};
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum KVRequest {
     Get { key: String },
     Set { key: String, val: String },
     Rm { key: String },
+    Scan { prefix: String },
+    Stats,
+    /// Handshake opening a connection, before the request itself.
+    Hello {
+        version: u32,
+        capabilities: Vec<Capability>,
+        /// Codecs the client speaks, from the most preferred.
+        #[serde(default)]
+        codecs: Vec<Codec>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum KVResponse {
     Ok(Option<String>),
-    Err(String),
+    Err { code: ErrorCode, message: String },
+    /// Negotiated version, capabilities and codec, answering a `Hello`.
+    Hello {
+        version: u32,
+        capabilities: Vec<Capability>,
+        #[serde(default)]
+        codec: Codec,
+    },
+}
+
+/// Machine-readable kind of a failed request, the message being meant for
+/// humans only.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    /// The key does not exist.
+    NotFound,
+    /// The stored value does not fit the operation.
+    WrongType,
+    /// A condition attached to the request does not hold.
+    PreconditionFailed,
+    /// The server does not accept writes.
+    ReadOnly,
+    /// The key or value of the request was rejected.
+    InvalidArgument,
+    /// The request could not be decoded.
+    Protocol,
+    /// The client speaks a protocol version the server no longer serves.
+    UnsupportedVersion,
+    /// Any other failure on the server side.
+    Internal,
+    /// Code of a newer server, handled as `Internal`.
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
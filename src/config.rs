@@ -0,0 +1,283 @@
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::addr::ServerAddr;
+use crate::engines::EngineOptions;
+use crate::error::{KVError, Result};
+
+const ENGINES: &[&str] = &["kvs", "sled", "lsm", "btree", "memory"];
+const LOG_LEVELS: &[&str] = &["critical", "error", "warn", "info", "debug", "trace"];
+
+/// When a write is acknowledged: once handed to the OS (`flush`, the
+/// default) or once synced to disk (`sync`).
+///
+/// sled cannot hand writes to the OS without syncing them, so it syncs
+/// every write either way. The memory engine only writes periodic dumps,
+/// and refuses `sync`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    Flush,
+    Sync,
+}
+
+impl FromStr for Durability {
+    type Err = KVError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "flush" => Ok(Durability::Flush),
+            "sync" => Ok(Durability::Sync),
+            _ => Err(KVError::InvalidConfig(format!("unknown durability {}", s))),
+        }
+    }
+}
+
+/// Protocol spoken by `kvs-server`: its own (`kvs`, the default) or RESP2
+/// (`resp`), so that Redis clients can talk to it.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerProtocol {
+    Kvs,
+    Resp,
+}
+
+impl FromStr for ServerProtocol {
+    type Err = KVError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "kvs" => Ok(ServerProtocol::Kvs),
+            "resp" => Ok(ServerProtocol::Resp),
+            _ => Err(KVError::InvalidConfig(format!("unknown protocol {}", s))),
+        }
+    }
+}
+
+/// lsm engine compaction thresholds.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompactionConfig {
+    pub memtable_limit: usize,
+    pub l0_trigger: usize,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        let options = EngineOptions::default();
+        CompactionConfig {
+            memtable_limit: options.memtable_limit,
+            l0_trigger: options.l0_compaction_trigger,
+        }
+    }
+}
+
+/// `kvs-server` settings, read from a TOML file such as:
+///
+/// ```toml
+/// addr = "127.0.0.1:4000"
+/// socket_mode = 0o660
+/// protocol = "resp"
+/// tls_cert = "/etc/kvs/cert.pem"
+/// tls_key = "/etc/kvs/key.pem"
+/// tls_client_ca = "/etc/kvs/clients.pem"
+/// engine = "lsm"
+/// data_dir = "/var/lib/kvs"
+/// threads = 4
+/// durability = "sync"
+/// cache_size = 1000
+/// log_level = "debug"
+///
+/// [compaction]
+/// memtable_limit = 4194304
+/// l0_trigger = 8
+/// ```
+///
+/// Every key is optional. `KVS_*` environment variables override the file,
+/// and command line flags override both. The HTTP gateway (`http_addr`) and
+/// the gRPC service (`grpc_addr`) only speak plain text, so they cannot be
+/// enabled along with TLS.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Comma-separated list of `ServerAddr` to listen on.
+    pub addr: String,
+    /// Permissions of the socket file when `addr` is `unix:PATH`.
+    pub socket_mode: Option<u32>,
+    pub protocol: ServerProtocol,
+    /// Address of the HTTP gateway, disabled when missing.
+    pub http_addr: Option<String>,
+    /// Address of the gRPC service, disabled when missing. Needs the `grpc`
+    /// feature.
+    pub grpc_addr: Option<String>,
+    /// PEM certificate chain served to clients, TLS being enabled along
+    /// with `tls_key`.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// PEM certificates of the CAs client certificates must be issued by,
+    /// clients without one being rejected.
+    pub tls_client_ca: Option<PathBuf>,
+    pub engine: Option<String>,
+    pub data_dir: Option<PathBuf>,
+    pub threads: usize,
+    pub durability: Durability,
+    pub compaction: CompactionConfig,
+    pub cache_size: usize,
+    pub log_level: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            addr: "127.0.0.1:4000".to_owned(),
+            socket_mode: None,
+            protocol: ServerProtocol::Kvs,
+            http_addr: None,
+            grpc_addr: None,
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            engine: None,
+            data_dir: None,
+            threads: 1,
+            durability: Durability::Flush,
+            compaction: CompactionConfig::default(),
+            cache_size: 0,
+            log_level: "info".to_owned(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content)
+            .map_err(|e| KVError::InvalidConfig(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Overrides settings with the `KVS_ADDR`, `KVS_SOCKET_MODE` (in octal),
+    /// `KVS_PROTOCOL`, `KVS_HTTP_ADDR`, `KVS_GRPC_ADDR`, `KVS_TLS_CERT`,
+    /// `KVS_TLS_KEY`, `KVS_TLS_CLIENT_CA`, `KVS_ENGINE`, `KVS_DATA_DIR`,
+    /// `KVS_THREADS`, `KVS_DURABILITY`, `KVS_MEMTABLE_LIMIT`,
+    /// `KVS_L0_TRIGGER`, `KVS_CACHE_SIZE` and `KVS_LOG_LEVEL` variables found
+    /// in `vars`, other variables being ignored.
+    pub fn apply_env<I>(&mut self, vars: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, val) in vars {
+            match name.as_str() {
+                "KVS_ADDR" => self.addr = val,
+                "KVS_SOCKET_MODE" => self.socket_mode = Some(parse_socket_mode(&val)?),
+                "KVS_PROTOCOL" => self.protocol = val.parse()?,
+                "KVS_HTTP_ADDR" => self.http_addr = Some(val),
+                "KVS_GRPC_ADDR" => self.grpc_addr = Some(val),
+                "KVS_TLS_CERT" => self.tls_cert = Some(PathBuf::from(val)),
+                "KVS_TLS_KEY" => self.tls_key = Some(PathBuf::from(val)),
+                "KVS_TLS_CLIENT_CA" => self.tls_client_ca = Some(PathBuf::from(val)),
+                "KVS_ENGINE" => self.engine = Some(val),
+                "KVS_DATA_DIR" => self.data_dir = Some(PathBuf::from(val)),
+                "KVS_THREADS" => self.threads = parse_var(&name, &val)?,
+                "KVS_DURABILITY" => self.durability = val.parse()?,
+                "KVS_MEMTABLE_LIMIT" => self.compaction.memtable_limit = parse_var(&name, &val)?,
+                "KVS_L0_TRIGGER" => self.compaction.l0_trigger = parse_var(&name, &val)?,
+                "KVS_CACHE_SIZE" => self.cache_size = parse_var(&name, &val)?,
+                "KVS_LOG_LEVEL" => self.log_level = val,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks every setting, so a bad configuration is reported before the
+    /// server opens the store or binds its address.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |msg: String| Err(KVError::InvalidConfig(msg));
+
+        // host names are only resolved when binding
+        for addr in ServerAddr::parse_list(&self.addr)? {
+            if let ServerAddr::Unix(_) = addr {
+                if !cfg!(unix) {
+                    return invalid(format!("{} needs Unix domain sockets", addr));
+                }
+            }
+        }
+        if let Some(mode) = self.socket_mode {
+            if mode > 0o777 {
+                return invalid(format!("invalid socket mode {:o}", mode));
+            }
+        }
+        if let Some(addr) = &self.http_addr {
+            if let ServerAddr::Unix(_) = addr.parse::<ServerAddr>()? {
+                return invalid(format!("the HTTP gateway cannot listen on {}", addr));
+            }
+        }
+        if let Some(addr) = &self.grpc_addr {
+            if !cfg!(feature = "grpc") {
+                return invalid("gRPC support was not compiled in, see the grpc feature".to_owned());
+            }
+            if let ServerAddr::Unix(_) = addr.parse::<ServerAddr>()? {
+                return invalid(format!("the gRPC service cannot listen on {}", addr));
+            }
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return invalid("tls_cert and tls_key go together".to_owned());
+        }
+        if self.tls_client_ca.is_some() && self.tls_cert.is_none() {
+            return invalid("tls_client_ca needs tls_cert and tls_key".to_owned());
+        }
+        // they would let clients around the certificate checks
+        if self.tls_cert.is_some() && self.http_addr.is_some() {
+            return invalid("the HTTP gateway does not support TLS".to_owned());
+        }
+        if self.tls_cert.is_some() && self.grpc_addr.is_some() {
+            return invalid("the gRPC service does not support TLS".to_owned());
+        }
+        if let Some(engine) = &self.engine {
+            if !ENGINES.contains(&engine.as_str()) {
+                return invalid(format!("unknown engine {}", engine));
+            }
+            if engine == "memory" && self.durability == Durability::Sync {
+                return invalid("the memory engine does not sync writes".to_owned());
+            }
+        }
+        if self.threads == 0 {
+            return invalid("threads must be at least 1".to_owned());
+        }
+        if self.compaction.memtable_limit == 0 {
+            return invalid("compaction.memtable_limit must be at least 1".to_owned());
+        }
+        if self.compaction.l0_trigger < 2 {
+            return invalid("compaction.l0_trigger must be at least 2".to_owned());
+        }
+        if !LOG_LEVELS.contains(&self.log_level.as_str()) {
+            return invalid(format!(
+                "unknown log level {}, expected one of {}",
+                self.log_level,
+                LOG_LEVELS.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn engine_options(&self) -> EngineOptions {
+        EngineOptions {
+            sync: self.durability == Durability::Sync,
+            memtable_limit: self.compaction.memtable_limit,
+            l0_compaction_trigger: self.compaction.l0_trigger,
+        }
+    }
+}
+
+/// Permissions written in octal, such as `660`.
+fn parse_socket_mode(val: &str) -> Result<u32> {
+    u32::from_str_radix(val, 8)
+        .map_err(|_| KVError::InvalidConfig(format!("invalid socket mode {}", val)))
+}
+
+fn parse_var<T: FromStr>(name: &str, val: &str) -> Result<T> {
+    val.parse()
+        .map_err(|_| KVError::InvalidConfig(format!("invalid value {} for {}", val, name)))
+}
@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::engines::btree::pager::{PageId, Pager};
+use crate::engines::btree::Node;
+use crate::error::Result;
+
+const HEADER_PAGE: PageId = 0;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    root: PageId,
+    next_page: PageId,
+    free: Vec<PageId>,
+}
+
+struct Frame {
+    node: Arc<Node>,
+    chain: Vec<PageId>,
+    dirty: bool,
+    last_used: u64,
+}
+
+/// Cache of decoded nodes on top of the pager.
+///
+/// Dirty nodes are never evicted: they only reach the data file through
+/// `checkpoint`, so the file always holds a consistent tree and the WAL
+/// covers everything written since.
+pub struct BufferPool {
+    pager: Pager,
+    frames: HashMap<PageId, Frame>,
+    capacity: usize,
+    tick: u64,
+    header: Header,
+    header_chain: Vec<PageId>,
+    header_dirty: bool,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl BufferPool {
+    pub fn open(mut pager: Pager, capacity: usize) -> Result<Self> {
+        let is_new = pager.is_empty()?;
+        let (header, header_chain) = if is_new {
+            let header = Header {
+                root: HEADER_PAGE + 1,
+                next_page: HEADER_PAGE + 2,
+                free: Vec::new(),
+            };
+            (header, vec![HEADER_PAGE])
+        } else {
+            let (data, chain) = pager.read_chain(HEADER_PAGE)?;
+            (serde_json::from_slice(&data)?, chain)
+        };
+
+        let mut pool = BufferPool {
+            pager,
+            frames: HashMap::new(),
+            capacity,
+            tick: 0,
+            header,
+            header_chain,
+            header_dirty: is_new,
+            hits: 0,
+            misses: 0,
+        };
+        if is_new {
+            let root = pool.header.root;
+            pool.insert_frame(root, Node::empty_leaf(), vec![root], true);
+            pool.checkpoint()?;
+        }
+
+        Ok(pool)
+    }
+
+    pub fn root(&self) -> PageId {
+        self.header.root
+    }
+
+    pub fn set_root(&mut self, root: PageId) {
+        self.header.root = root;
+        self.header_dirty = true;
+    }
+
+    pub fn cached(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.frames.len() > self.capacity
+    }
+
+    pub fn read(&mut self, page_id: PageId) -> Result<Arc<Node>> {
+        self.tick += 1;
+        if let Some(frame) = self.frames.get_mut(&page_id) {
+            frame.last_used = self.tick;
+            self.hits += 1;
+            return Ok(Arc::clone(&frame.node));
+        }
+
+        self.misses += 1;
+        let (data, chain) = self.pager.read_chain(page_id)?;
+        let node: Node = serde_json::from_slice(&data)?;
+        self.evict_clean();
+        Ok(self.insert_frame(page_id, node, chain, false))
+    }
+
+    /// Replaces the node stored at `page_id`.
+    pub fn write(&mut self, page_id: PageId, node: Node) -> Result<()> {
+        self.tick += 1;
+        match self.frames.get_mut(&page_id) {
+            Some(frame) => {
+                frame.node = Arc::new(node);
+                frame.dirty = true;
+                frame.last_used = self.tick;
+            }
+            None => {
+                // evicted since it was read, only its chain is needed back
+                let (_, chain) = self.pager.read_chain(page_id)?;
+                self.insert_frame(page_id, node, chain, true);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn alloc(&mut self, node: Node) -> PageId {
+        let page_id = self.alloc_page();
+        self.tick += 1;
+        self.insert_frame(page_id, node, vec![page_id], true);
+        page_id
+    }
+
+    /// Writes every dirty node and the header through the pager.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let dirty: Vec<PageId> = self
+            .frames
+            .iter()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(page_id, _)| *page_id)
+            .collect();
+        if dirty.is_empty() && !self.header_dirty {
+            return Ok(());
+        }
+
+        let mut images = Vec::new();
+        for page_id in dirty {
+            let data = serde_json::to_vec(&*self.frames[&page_id].node)?;
+            let mut chain = std::mem::take(&mut self.frames.get_mut(&page_id).unwrap().chain);
+            let needed = Pager::pages_for(data.len());
+            while chain.len() < needed {
+                chain.push(self.alloc_page());
+            }
+            while chain.len() > needed {
+                let page = chain.pop().unwrap();
+                self.header.free.push(page);
+            }
+            images.extend(Pager::page_images(&data, &chain));
+
+            let frame = self.frames.get_mut(&page_id).unwrap();
+            frame.chain = chain;
+            frame.dirty = false;
+        }
+
+        // growing the header chain changes the header itself, hence the loop
+        let data = loop {
+            let data = serde_json::to_vec(&self.header)?;
+            if self.header_chain.len() >= Pager::pages_for(data.len()) {
+                break data;
+            }
+            self.header_chain.push(self.header.next_page);
+            self.header.next_page += 1;
+        };
+        images.extend(Pager::page_images(&data, &self.header_chain));
+
+        self.pager.write_checkpoint(&images)?;
+        self.header_dirty = false;
+        self.evict_clean();
+        Ok(())
+    }
+
+    fn alloc_page(&mut self) -> PageId {
+        self.header_dirty = true;
+        match self.header.free.pop() {
+            Some(page_id) => page_id,
+            None => {
+                self.header.next_page += 1;
+                self.header.next_page - 1
+            }
+        }
+    }
+
+    fn insert_frame(
+        &mut self,
+        page_id: PageId,
+        node: Node,
+        chain: Vec<PageId>,
+        dirty: bool,
+    ) -> Arc<Node> {
+        let node = Arc::new(node);
+        self.frames.insert(
+            page_id,
+            Frame {
+                node: Arc::clone(&node),
+                chain,
+                dirty,
+                last_used: self.tick,
+            },
+        );
+        node
+    }
+
+    /// Evicts least recently used clean nodes until under capacity.
+    fn evict_clean(&mut self) {
+        while self.frames.len() >= self.capacity {
+            let victim = self
+                .frames
+                .iter()
+                .filter(|(_, frame)| !frame.dirty)
+                .min_by_key(|(_, frame)| frame.last_used)
+                .map(|(page_id, _)| *page_id);
+            match victim {
+                Some(page_id) => self.frames.remove(&page_id),
+                None => return,
+            };
+        }
+    }
+}
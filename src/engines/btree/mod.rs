@@ -0,0 +1,354 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use self::buffer_pool::BufferPool;
+use self::pager::{PageId, Pager, PAGE_SIZE};
+use crate::common_struct::KVPair;
+use crate::engines::meta::EngineMeta;
+use crate::engines::wal::Wal;
+use crate::engines::{EngineOptions, KVEngine};
+use crate::error::{KVError, Result};
+use crate::lock::DirLock;
+
+mod buffer_pool;
+mod pager;
+
+const ENGINE_NAME: &str = "btree";
+const FORMAT_VERSION: u32 = 1;
+
+const BTREE_DIR: &str = "btree";
+const DATA_FILE: &str = "btree.db";
+const WAL_FILE: &str = "btree.wal";
+const CKPT_FILE: &str = "btree.ckpt";
+
+const MAX_KEYS: usize = 64;
+const POOL_CAPACITY: usize = 1024;
+const WAL_CHECKPOINT_SIZE: u64 = 4 << 20;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Node {
+    Internal {
+        keys: Vec<String>,
+        children: Vec<PageId>,
+    },
+    Leaf {
+        keys: Vec<String>,
+        vals: Vec<String>,
+        next: Option<PageId>,
+    },
+}
+
+impl Node {
+    fn empty_leaf() -> Self {
+        Node::Leaf {
+            keys: Vec::new(),
+            vals: Vec::new(),
+            next: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WalRecord {
+    key: String,
+    val: Option<String>,
+}
+
+/// Page-oriented B+tree engine with a buffer pool and a write-ahead log.
+///
+/// Writes are logged, applied to the buffer pool, and reach the data file at
+/// checkpoints. Removing keys never merges nodes.
+#[derive(Clone)]
+pub struct BTreeKVEngine(Arc<Mutex<BTree>>);
+
+impl BTreeKVEngine {
+    pub fn open(dir_path: &Path) -> Result<Self> {
+        BTreeKVEngine::open_with(dir_path, &EngineOptions::default())
+    }
+
+    pub fn open_with(dir_path: &Path, options: &EngineOptions) -> Result<Self> {
+        let lock = DirLock::exclusive(dir_path)?;
+        let mut params = BTreeMap::new();
+        params.insert("page_size".to_owned(), PAGE_SIZE.to_string());
+        params.insert("max_keys".to_owned(), MAX_KEYS.to_string());
+        let meta = EngineMeta::open(dir_path, ENGINE_NAME, FORMAT_VERSION, params)?;
+        if let Some(page_size) = meta.params.get("page_size") {
+            if *page_size != PAGE_SIZE.to_string() {
+                return Err(KVError::StringError(format!(
+                    "btree directory uses {} byte pages, {} expected",
+                    page_size, PAGE_SIZE
+                )));
+            }
+        }
+
+        let dir = dir_path.join(BTREE_DIR);
+        fs::create_dir_all(&dir)?;
+        let pager = Pager::open(&dir.join(DATA_FILE), &dir.join(CKPT_FILE))?;
+        let pool = BufferPool::open(pager, POOL_CAPACITY)?;
+
+        let wal_path = dir.join(WAL_FILE);
+        let mut tree = BTree {
+            pool,
+            wal: Wal::open(&wal_path, options.sync)?,
+            _lock: lock,
+        };
+        for record in Wal::<WalRecord>::replay(&wal_path)? {
+            match record.val {
+                Some(val) => tree.insert(record.key, val)?,
+                None => {
+                    tree.delete(&record.key)?;
+                }
+            }
+        }
+        tree.checkpoint()?;
+
+        Ok(BTreeKVEngine(Arc::new(Mutex::new(tree))))
+    }
+}
+
+impl KVEngine for BTreeKVEngine {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        let mut tree = self.0.lock().unwrap();
+        tree.wal.append(&WalRecord {
+            key: key.to_owned(),
+            val: Some(value.to_owned()),
+        })?;
+        tree.insert(key, value)?;
+        tree.maybe_checkpoint()
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.0.lock().unwrap().get(&key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        let mut tree = self.0.lock().unwrap();
+        if tree.get(&key)?.is_none() {
+            return Err(KVError::FailGet(key));
+        }
+        tree.wal.append(&WalRecord {
+            key: key.to_owned(),
+            val: None,
+        })?;
+        tree.delete(&key)?;
+        tree.maybe_checkpoint()
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        self.0.lock().unwrap().scan(prefix)
+    }
+
+    fn scan_from(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<Vec<KVPair>> {
+        self.0.lock().unwrap().scan_from(prefix, after, limit)
+    }
+
+    fn stats(&self) -> HashMap<String, u64> {
+        let tree = self.0.lock().unwrap();
+        let mut stats = HashMap::new();
+        stats.insert("pool_hits".to_owned(), tree.pool.hits);
+        stats.insert("pool_misses".to_owned(), tree.pool.misses);
+        stats.insert("pool_nodes".to_owned(), tree.pool.cached() as u64);
+        stats
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.0.lock().unwrap().checkpoint()
+    }
+}
+
+struct BTree {
+    pool: BufferPool,
+    wal: Wal<WalRecord>,
+    _lock: DirLock,
+}
+
+impl BTree {
+    fn get(&mut self, key: &str) -> Result<Option<String>> {
+        let mut page_id = self.pool.root();
+        loop {
+            match &*self.pool.read(page_id)? {
+                Node::Internal { keys, children } => {
+                    page_id = children[child_index(keys, key)];
+                }
+                Node::Leaf { keys, vals, .. } => {
+                    let found = keys.binary_search_by(|k| k.as_str().cmp(key));
+                    return Ok(found.ok().map(|idx| vals[idx].to_owned()));
+                }
+            }
+        }
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<KVPair>> {
+        self.scan_from(prefix, None, usize::MAX)
+    }
+
+    /// Descends to the leaf where the first key to answer would sit, then
+    /// walks the leaf chain while keys still match.
+    fn scan_from(
+        &mut self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<KVPair>> {
+        let start = match after {
+            Some(after) if after > prefix => after,
+            _ => prefix,
+        };
+        let mut page_id = self.pool.root();
+        while let Node::Internal { keys, children } = &*self.pool.read(page_id)? {
+            page_id = children[child_index(keys, start)];
+        }
+
+        let mut pairs = Vec::new();
+        let mut leaf = Some(page_id);
+        while let Some(page_id) = leaf {
+            let node = self.pool.read(page_id)?;
+            if let Node::Leaf { keys, vals, next } = &*node {
+                for (key, val) in keys.iter().zip(vals) {
+                    if pairs.len() >= limit {
+                        return Ok(pairs);
+                    }
+                    if after.map_or(false, |after| key.as_str() <= after) {
+                        continue;
+                    }
+                    if key.starts_with(prefix) {
+                        pairs.push(KVPair::new(key.to_owned(), val.to_owned()));
+                    } else if key.as_str() > prefix {
+                        return Ok(pairs);
+                    }
+                }
+                leaf = *next;
+            } else {
+                leaf = None;
+            }
+        }
+
+        Ok(pairs)
+    }
+
+    fn insert(&mut self, key: String, val: String) -> Result<()> {
+        let root = self.pool.root();
+        if let Some((separator, right)) = self.insert_at(root, key, val)? {
+            let new_root = self.pool.alloc(Node::Internal {
+                keys: vec![separator],
+                children: vec![root, right],
+            });
+            self.pool.set_root(new_root);
+        }
+        Ok(())
+    }
+
+    /// Inserts into the subtree rooted at `page_id`, returning the separator
+    /// key and page of the new right sibling if the node had to split.
+    fn insert_at(
+        &mut self,
+        page_id: PageId,
+        key: String,
+        val: String,
+    ) -> Result<Option<(String, PageId)>> {
+        let mut node = (*self.pool.read(page_id)?).clone();
+
+        let split = match &mut node {
+            Node::Leaf { keys, vals, next } => {
+                match keys.binary_search(&key) {
+                    Ok(idx) => vals[idx] = val,
+                    Err(idx) => {
+                        keys.insert(idx, key);
+                        vals.insert(idx, val);
+                    }
+                }
+
+                if keys.len() > MAX_KEYS {
+                    let mid = keys.len() / 2;
+                    let right_keys = keys.split_off(mid);
+                    let separator = right_keys[0].to_owned();
+                    let right = self.pool.alloc(Node::Leaf {
+                        keys: right_keys,
+                        vals: vals.split_off(mid),
+                        next: next.take(),
+                    });
+                    *next = Some(right);
+                    Some((separator, right))
+                } else {
+                    None
+                }
+            }
+            Node::Internal { keys, children } => {
+                let idx = child_index(keys, &key);
+                match self.insert_at(children[idx], key, val)? {
+                    Some((child_separator, child_right)) => {
+                        keys.insert(idx, child_separator);
+                        children.insert(idx + 1, child_right);
+                    }
+                    // the child absorbed the write, this node is unchanged
+                    None => return Ok(None),
+                }
+
+                if keys.len() > MAX_KEYS {
+                    let mid = keys.len() / 2;
+                    let mut right_keys = keys.split_off(mid);
+                    let separator = right_keys.remove(0);
+                    let right = self.pool.alloc(Node::Internal {
+                        keys: right_keys,
+                        children: children.split_off(mid + 1),
+                    });
+                    Some((separator, right))
+                } else {
+                    None
+                }
+            }
+        };
+
+        self.pool.write(page_id, node)?;
+        Ok(split)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<bool> {
+        let mut page_id = self.pool.root();
+        loop {
+            let node = self.pool.read(page_id)?;
+            match &*node {
+                Node::Internal { keys, children } => {
+                    page_id = children[child_index(keys, key)];
+                }
+                Node::Leaf { keys, .. } => {
+                    let idx = match keys.binary_search_by(|k| k.as_str().cmp(key)) {
+                        Ok(idx) => idx,
+                        Err(_) => return Ok(false),
+                    };
+
+                    let mut node = (*node).clone();
+                    if let Node::Leaf { keys, vals, .. } = &mut node {
+                        keys.remove(idx);
+                        vals.remove(idx);
+                    }
+                    self.pool.write(page_id, node)?;
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    fn maybe_checkpoint(&mut self) -> Result<()> {
+        if self.pool.is_full() || self.wal.size() >= WAL_CHECKPOINT_SIZE {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) -> Result<()> {
+        self.pool.checkpoint()?;
+        self.wal.truncate()
+    }
+}
+
+/// Index of the child of an internal node whose subtree may hold `key`.
+fn child_index(keys: &[String], key: &str) -> usize {
+    match keys.binary_search_by(|k| k.as_str().cmp(key)) {
+        Ok(idx) => idx + 1,
+        Err(idx) => idx,
+    }
+}
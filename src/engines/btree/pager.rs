@@ -0,0 +1,160 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{KVError, Result};
+
+pub type PageId = u32;
+
+pub const PAGE_SIZE: usize = 4096;
+const PAGE_HEADER_LEN: usize = 8;
+const PAGE_PAYLOAD: usize = PAGE_SIZE - PAGE_HEADER_LEN;
+const CKPT_MAGIC: &[u8; 8] = b"KVSCKPT1";
+
+/// Raw page I/O on the data file.
+///
+/// Every page starts with the id of the next page of its chain (0 for none)
+/// and the payload length, both big-endian `u32`, so a node larger than a
+/// page spills over a chain of pages.
+pub struct Pager {
+    file: File,
+    ckpt_path: PathBuf,
+}
+
+impl Pager {
+    pub fn open(path: &Path, ckpt_path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+        let mut pager = Pager {
+            file,
+            ckpt_path: ckpt_path.to_path_buf(),
+        };
+        pager.recover_checkpoint()?;
+        Ok(pager)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.file.metadata()?.len() == 0)
+    }
+
+    /// Reads the payload stored in the chain starting at `head`, along with
+    /// the ids of the pages of the chain.
+    pub fn read_chain(&mut self, head: PageId) -> Result<(Vec<u8>, Vec<PageId>)> {
+        let mut data = Vec::new();
+        let mut chain = Vec::new();
+        let mut page_id = head;
+        let mut page = [0; PAGE_SIZE];
+
+        loop {
+            if chain.contains(&page_id) {
+                return Err(KVError::StringError(format!("page chain loop at {}", page_id)));
+            }
+            self.file
+                .seek(SeekFrom::Start(page_id as u64 * PAGE_SIZE as u64))?;
+            self.file.read_exact(&mut page)?;
+            chain.push(page_id);
+
+            let next = read_u32(&page[0..4]);
+            let len = (read_u32(&page[4..8]) as usize).min(PAGE_PAYLOAD);
+            data.extend_from_slice(&page[PAGE_HEADER_LEN..PAGE_HEADER_LEN + len]);
+
+            if next == 0 {
+                return Ok((data, chain));
+            }
+            page_id = next;
+        }
+    }
+
+    /// Number of pages needed to store `len` bytes.
+    pub fn pages_for(len: usize) -> usize {
+        ((len + PAGE_PAYLOAD - 1) / PAGE_PAYLOAD).max(1)
+    }
+
+    /// Splits `data` into page images laid out on `chain`, which must hold
+    /// at least `pages_for(data.len())` pages, trailing pages staying empty.
+    pub fn page_images(data: &[u8], chain: &[PageId]) -> Vec<(PageId, Vec<u8>)> {
+        let chunks: Vec<&[u8]> = data.chunks(PAGE_PAYLOAD).collect();
+
+        chain
+            .iter()
+            .enumerate()
+            .map(|(idx, page_id)| {
+                let chunk = chunks.get(idx).cloned().unwrap_or(&[]);
+                let next = chain.get(idx + 1).cloned().unwrap_or(0);
+                let mut page = vec![0; PAGE_SIZE];
+                page[0..4].copy_from_slice(&next.to_be_bytes());
+                page[4..8].copy_from_slice(&(chunk.len() as u32).to_be_bytes());
+                page[PAGE_HEADER_LEN..PAGE_HEADER_LEN + chunk.len()].copy_from_slice(chunk);
+                (*page_id, page)
+            })
+            .collect()
+    }
+
+    /// Writes `images` to the data file so that a crash at any point leaves
+    /// either the old or the new version of every page: images go to the
+    /// checkpoint file first, then in place.
+    pub fn write_checkpoint(&mut self, images: &[(PageId, Vec<u8>)]) -> Result<()> {
+        {
+            let mut writer = BufWriter::new(File::create(&self.ckpt_path)?);
+            for (page_id, page) in images {
+                writer.write_all(&page_id.to_be_bytes())?;
+                writer.write_all(page)?;
+            }
+            writer.write_all(&(images.len() as u32).to_be_bytes())?;
+            writer.write_all(CKPT_MAGIC)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+
+        self.write_in_place(images)?;
+        fs::remove_file(&self.ckpt_path)?;
+        Ok(())
+    }
+
+    fn write_in_place(&mut self, images: &[(PageId, Vec<u8>)]) -> Result<()> {
+        for (page_id, page) in images {
+            self.file
+                .seek(SeekFrom::Start(*page_id as u64 * PAGE_SIZE as u64))?;
+            self.file.write_all(page)?;
+        }
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    /// Re-applies a complete checkpoint file left by a crash, a truncated
+    /// one is discarded since the data file was not touched yet.
+    fn recover_checkpoint(&mut self) -> Result<()> {
+        if !self.ckpt_path.exists() {
+            return Ok(());
+        }
+
+        let mut buf = Vec::new();
+        BufReader::new(File::open(&self.ckpt_path)?).read_to_end(&mut buf)?;
+
+        let record_len = 4 + PAGE_SIZE;
+        let trailer_len = 4 + CKPT_MAGIC.len();
+        let complete = buf.len() >= trailer_len
+            && buf.ends_with(CKPT_MAGIC)
+            && read_u32(&buf[buf.len() - trailer_len..]) as usize * record_len
+                == buf.len() - trailer_len;
+
+        if complete {
+            let images: Vec<(PageId, Vec<u8>)> = buf[..buf.len() - trailer_len]
+                .chunks(record_len)
+                .map(|record| (read_u32(&record[0..4]), record[4..].to_vec()))
+                .collect();
+            self.write_in_place(&images)?;
+        }
+        fs::remove_file(&self.ckpt_path)?;
+        Ok(())
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[0..4]);
+    u32::from_be_bytes(buf)
+}
@@ -1,6 +1,8 @@
 use crate::common_struct::KVPair;
-use crate::engines::KVEngine;
+use crate::engines::meta::EngineMeta;
+use crate::engines::{EngineOptions, KVEngine};
 use crate::error::{KVError, Result};
+use crate::lock::DirLock;
 
 use serde::Deserialize;
 use serde_json::Deserializer;
@@ -10,50 +12,78 @@ use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
-const COMPACTION_THRESHOLD: u64 = 1024;
+const ENGINE_NAME: &str = "kvs";
+const FORMAT_VERSION: u32 = 1;
 
-//#[derive(Clone)]
 pub struct KVStore {
     log_path: Arc<PathBuf>,
-    index: Arc<HashMap<String, KVEntry>>,
-    writer: Arc<Mutex<BufWriterPos>>,
+    index: Index,
+    writer: Option<Arc<Mutex<BufWriterPos>>>,
     readers: BufReaderMap,
+    _lock: Arc<DirLock>,
 }
 
 impl KVStore {
     pub fn open(dir_path: &Path) -> Result<KVStore> {
+        KVStore::open_with(dir_path, &EngineOptions::default())
+    }
+
+    pub fn open_with(dir_path: &Path, options: &EngineOptions) -> Result<KVStore> {
         match dir_path.to_str() {
             Some(dir_str) => {
-                let sled_path = format!("{}{}", dir_str, "/my_old_db");
-                if Path::new(&sled_path).exists() {
-                    return Err(KVError::WrongEngine);
-                }
+                let lock = DirLock::exclusive(dir_path)?;
+                EngineMeta::open(dir_path, ENGINE_NAME, FORMAT_VERSION, BTreeMap::new())?;
 
                 let log_path = Path::new(dir_str).join("log_file.txt");
                 let readers = BufReaderMap::new(&log_path);
-                let writer = BufWriterPos::new(&log_path)?;
+                let index = Arc::new(RwLock::new(readers.generate_index()?));
+                let writer = BufWriterPos::new(&log_path, Arc::clone(&index), options.sync)?;
 
                 Ok(KVStore {
                     log_path: Arc::new(log_path),
-                    index: Arc::new(HashMap::new()),
-                    writer: Arc::new(Mutex::new(writer)),
+                    index,
+                    writer: Some(Arc::new(Mutex::new(writer))),
                     readers: readers,
+                    _lock: Arc::new(lock),
                 })
             }
             None => Err(KVError::None),
         }
     }
+
+    /// Opens an existing store without ever writing to it, sharing the
+    /// directory with other read-only opens but not with a writer.
+    pub fn open_read_only(dir_path: &Path) -> Result<KVStore> {
+        let lock = DirLock::shared(dir_path)?;
+        EngineMeta::open_read_only(dir_path, ENGINE_NAME, FORMAT_VERSION)?;
+
+        let log_path = dir_path.join("log_file.txt");
+        let readers = BufReaderMap::new(&log_path);
+        let index = readers.generate_index()?;
+
+        Ok(KVStore {
+            log_path: Arc::new(log_path),
+            index: Arc::new(RwLock::new(index)),
+            writer: None,
+            readers,
+            _lock: Arc::new(lock),
+        })
+    }
+
+    fn writer(&self) -> Result<&Arc<Mutex<BufWriterPos>>> {
+        self.writer.as_ref().ok_or(KVError::ReadOnly)
+    }
 }
 
 impl KVEngine for KVStore {
     fn set(&self, key: String, value: String) -> Result<()> {
-        self.writer.lock().unwrap().set(key, value)
+        self.writer()?.lock().unwrap().set(key, value)
     }
 
     fn get(&self, key: String) -> Result<Option<String>> {
-        match self.index.get(&key) {
+        match self.index.read().unwrap().get(&key) {
             Some(entry) => {
                 if entry.rm {
                     Ok(None)
@@ -67,7 +97,37 @@ impl KVEngine for KVStore {
     }
 
     fn remove(&self, key: String) -> Result<()> {
-        self.writer.lock().unwrap().remove(key)
+        self.writer()?.lock().unwrap().remove(key)
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        // replay the whole log, the latest entry of each key winning
+        let reader = BufReader::new(File::open(self.log_path.as_path())?);
+        let stream = Deserializer::from_reader(reader).into_iter::<KVPair>();
+        let mut live = BTreeMap::new();
+        for kv in stream {
+            let kv = kv?;
+            if !kv.key.starts_with(prefix) {
+                continue;
+            }
+            if kv.val == "rm" {
+                live.remove(&kv.key);
+            } else {
+                live.insert(kv.key, kv.val);
+            }
+        }
+
+        Ok(live
+            .into_iter()
+            .map(|(key, val)| KVPair::new(key, val))
+            .collect())
+    }
+
+    fn flush(&self) -> Result<()> {
+        match &self.writer {
+            Some(writer) => writer.lock().unwrap().sync(),
+            None => Ok(()),
+        }
     }
 }
 
@@ -76,8 +136,9 @@ impl Clone for KVStore {
         KVStore {
             log_path: Arc::clone(&self.log_path),
             index: Arc::clone(&self.index),
-            writer: Arc::clone(&self.writer),
+            writer: self.writer.as_ref().map(Arc::clone),
             readers: BufReaderMap::new(&self.log_path),
+            _lock: Arc::clone(&self._lock),
         }
     }
 }
@@ -105,37 +166,42 @@ impl BufReaderMap {
         reader.read_entry(&entry)
     }
 
-    fn generate_index(&self) -> HashMap<String, KVEntry> {
-        let index = HashMap::new();
-        index
+    /// Index of the live keys of the log, empty while there is no log.
+    fn generate_index(&self) -> Result<HashMap<String, KVEntry>> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+        let mut reader = BufReaderPos::new(File::open(self.path.as_path())?);
+        Ok(reader.generate_index())
     }
 }
 
-//#[derive(Clone)]
+/// Entries of the live keys of the log, shared by every clone of a store
+/// and updated by its writer.
+type Index = Arc<RwLock<HashMap<String, KVEntry>>>;
+
 struct BufWriterPos {
-    reader: BufReaderMap,
-    index: Arc<HashMap<String, KVEntry>>,
-    writer: Arc<BufWriter<File>>,
+    index: Index,
+    writer: BufWriter<File>,
     pos: usize,
+    sync: bool,
 }
 
 impl BufWriterPos {
-    fn new(file_path: &PathBuf) -> Result<Self> {
+    fn new(file_path: &PathBuf, index: Index, sync: bool) -> Result<Self> {
         let file = OpenOptions::new()
             .write(true)
             .create(true)
             .append(true)
             .open(file_path)?;
-
-        let writer = BufWriter::new(file);
-        let reader = BufReaderMap::new(file_path);
-        let index = reader.generate_index();
+        // appended entries start at the end of the log
+        let pos = file.metadata()?.len() as usize;
 
         Ok(BufWriterPos {
-            reader: reader,
-            index: Arc::new(index),
-            writer: Arc::new(writer),
-            pos: 0,
+            index,
+            writer: BufWriter::new(file),
+            pos,
+            sync,
         })
     }
 
@@ -146,84 +212,53 @@ impl BufWriterPos {
             len: len,
             pos: pos,
         };
-        Arc::get_mut(&mut self.index).unwrap().insert(key, entry);
-
-        // self.compaction()?;
+        self.index.write().unwrap().insert(key, entry);
 
         Ok(())
     }
 
     pub fn remove(&mut self, key: String) -> Result<()> {
-        self.append_log_file(&key, "rm")?;
-        //let index = Arc::get_mut(&mut self.index).unwrap();
-        if let Some(entry) = Arc::get_mut(&mut self.index).unwrap().get_mut(&key) {
-            entry.rm = true;
-            //self.compaction()?;
-
-            Ok(())
-        } else {
-            Err(KVError::FailRemove)
+        if !self.index.read().unwrap().contains_key(&key) {
+            return Err(KVError::FailRemove);
         }
+        self.append_log_file(&key, "rm")?;
+        self.index.write().unwrap().remove(&key);
+
+        Ok(())
     }
 
+    /// Appends the entry, answering its length and the offset it starts at.
     fn append_log_file(&mut self, key: &str, val: &str) -> Result<(usize, usize)> {
-        // println!("                  self.pos: {}", self.pos);
-
         let kv_pair: KVPair = KVPair::new(key.to_owned(), val.to_owned());
         let mut buf = vec![];
         serde_json::to_writer(&mut buf, &kv_pair)?;
         let len = buf.len();
+        let pos = self.pos;
         self.write_all(&buf)?;
         self.flush()?;
-
-        // println!("                  start_pos -> self.pos: {} -> {}", start_pos, self.pos);
-
-        Ok((len, self.pos))
-    }
-
-    pub fn compaction(&mut self) -> Result<()> {
-        /*self.readers.reader.seek(SeekFrom::Start(0))?;
-        let stream = Deserializer::from_reader(self.readers.reader).into_iter::<KVPair>();
-
-        //let index = &mut self.index;
-        let mut kv_map = HashMap::new();
-        stream.for_each(|res_kv| {
-            if let Ok(kv) = res_kv {
-                if let Some(entry) = self.index.get(&kv.key) {
-                    if !(entry.rm) {
-                        kv_map.insert(kv.key, kv.val);
-                    }
-                }
-            };
-        });
-
-        std::fs::remove_file(self.log_path)?;
-        // let writer = BufWriterPos::new(self.log_path.to_string())?;
-        // self.writer = Arc::new(writer);
-
-        // println!("              compaction - append log file");
-        for (k, v) in kv_map.iter() {
-            // self.writer.append_log_file(k, v)?;
+        if self.sync {
+            self.writer.get_ref().sync_data()?;
         }
 
-        let file = File::open(self.log_path)?;
-        self.readers = BufReaderPos::new(file);*/
+        Ok((len, pos))
+    }
 
+    fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.writer.get_ref().sync_data()?;
         Ok(())
     }
 }
 
 impl Write for BufWriterPos {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let writer = Arc::get_mut(&mut self.writer).unwrap();
-        self.pos += writer.write(buf)?;
-        Ok(self.pos)
+        let written = self.writer.write(buf)?;
+        self.pos += written;
+        Ok(written)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        let writer = Arc::get_mut(&mut self.writer).unwrap();
-        writer.flush()?;
-        Ok(())
+        self.writer.flush()
     }
 }
 
@@ -248,7 +283,6 @@ impl<T: Seek + Read> BufReaderPos<T> {
     }
 
     fn generate_index(&mut self) -> HashMap<String, KVEntry> {
-        //let reader = Arc::get_mut(&mut self.reader).unwrap();
         let mut stream = Deserializer::from_reader(&mut self.reader).into_iter::<KVPair>();
         let mut index = HashMap::new();
 
@@ -262,12 +296,13 @@ impl<T: Seek + Read> BufReaderPos<T> {
                             len: len,
                             pos: self.pos,
                         };
-                        index.insert(kv.key, entry)
+                        index.insert(kv.key, entry);
                     } else {
-                        None
+                        index.remove(&kv.key);
                     }
                 }
-                Err(_) => None,
+                // a torn entry ends the log
+                Err(_) => break,
             };
             self.pos += len;
         }
@@ -276,8 +311,6 @@ impl<T: Seek + Read> BufReaderPos<T> {
     }
 
     fn read_entry(&mut self, entry: &KVEntry) -> Result<String> {
-        //let reader = Arc::get_mut(&mut self.reader).unwrap();
-        //reader.seek(SeekFrom::Start(entry.pos as u64))?;
         self.reader.seek(SeekFrom::Start(entry.pos as u64))?;
         let mut deserializer = Deserializer::from_reader(&mut self.reader);
         let KVPair { val, .. } = KVPair::deserialize(&mut deserializer)?;
@@ -0,0 +1,166 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::common_struct::KVPair;
+use crate::engines::KVEngine;
+use crate::error::Result;
+
+/// Size-bounded LRU value cache in front of any `KVEngine`.
+///
+/// Values are cached on `get` and invalidated on `set`/`remove`.
+#[derive(Clone)]
+pub struct KVCache<E: KVEngine> {
+    engine: E,
+    lru: Arc<Mutex<LruMap>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u64,
+    pub capacity: u64,
+}
+
+impl<E: KVEngine> KVCache<E> {
+    /// `capacity` is the maximum number of cached values.
+    pub fn new(engine: E, capacity: usize) -> Self {
+        KVCache {
+            engine,
+            lru: Arc::new(Mutex::new(LruMap::new(capacity))),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        let lru = self.lru.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: lru.entries.len() as u64,
+            capacity: lru.capacity as u64,
+        }
+    }
+}
+
+impl<E: KVEngine> KVEngine for KVCache<E> {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.engine.set(key.to_owned(), value)?;
+        self.lru.lock().unwrap().invalidate(&key);
+        Ok(())
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        let epoch = {
+            let mut lru = self.lru.lock().unwrap();
+            if let Some(val) = lru.get(&key) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(val));
+            }
+            lru.epoch
+        };
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let val = self.engine.get(key.to_owned())?;
+        if let Some(val) = &val {
+            // skip caching if a write invalidated entries while reading the engine
+            let mut lru = self.lru.lock().unwrap();
+            if lru.epoch == epoch {
+                lru.insert(key, val.to_owned());
+            }
+        }
+
+        Ok(val)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        let res = self.engine.remove(key.to_owned());
+        self.lru.lock().unwrap().invalidate(&key);
+        res
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        self.engine.scan(prefix)
+    }
+
+    fn scan_from(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<Vec<KVPair>> {
+        self.engine.scan_from(prefix, after, limit)
+    }
+
+    fn stats(&self) -> HashMap<String, u64> {
+        let mut stats = self.engine.stats();
+        let cache_stats = self.cache_stats();
+        stats.insert("cache_hits".to_owned(), cache_stats.hits);
+        stats.insert("cache_misses".to_owned(), cache_stats.misses);
+        stats.insert("cache_entries".to_owned(), cache_stats.entries);
+        stats.insert("cache_capacity".to_owned(), cache_stats.capacity);
+        stats
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.engine.flush()
+    }
+}
+
+struct LruMap {
+    capacity: usize,
+    tick: u64,
+    epoch: u64,
+    entries: HashMap<String, (String, u64)>,
+    order: BTreeMap<u64, String>,
+}
+
+impl LruMap {
+    fn new(capacity: usize) -> Self {
+        LruMap {
+            capacity,
+            tick: 0,
+            epoch: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<String> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (val, last_used) = self.entries.get_mut(key)?;
+        self.order.remove(last_used);
+        *last_used = tick;
+        self.order.insert(tick, key.to_owned());
+        Some(val.to_owned())
+    }
+
+    fn insert(&mut self, key: String, val: String) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.tick += 1;
+        if let Some((_, last_used)) = self.entries.insert(key.to_owned(), (val, self.tick)) {
+            self.order.remove(&last_used);
+        }
+        self.order.insert(self.tick, key);
+
+        while self.entries.len() > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            if let Some(old_key) = self.order.remove(&oldest) {
+                self.entries.remove(&old_key);
+            }
+        }
+    }
+
+    fn invalidate(&mut self, key: &str) {
+        self.epoch += 1;
+        if let Some((_, last_used)) = self.entries.remove(key) {
+            self.order.remove(&last_used);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::common_struct::KVPair;
+use crate::engines::KVEngine;
+use crate::error::Result;
+
+/// Counts operations, errors and time spent in the wrapped engine.
+#[derive(Clone)]
+pub struct KVMetrics<E: KVEngine> {
+    engine: E,
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    gets: AtomicU64,
+    sets: AtomicU64,
+    removes: AtomicU64,
+    scans: AtomicU64,
+    errors: AtomicU64,
+    get_micros: AtomicU64,
+    set_micros: AtomicU64,
+    remove_micros: AtomicU64,
+    scan_micros: AtomicU64,
+}
+
+impl<E: KVEngine> KVMetrics<E> {
+    pub fn new(engine: E) -> Self {
+        KVMetrics {
+            engine,
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    fn record<T>(&self, count: &AtomicU64, micros: &AtomicU64, start: Instant, res: &Result<T>) {
+        count.fetch_add(1, Ordering::Relaxed);
+        micros.fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        if res.is_err() {
+            self.counters.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<E: KVEngine> KVEngine for KVMetrics<E> {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        let start = Instant::now();
+        let res = self.engine.set(key, value);
+        let counters = &self.counters;
+        self.record(&counters.sets, &counters.set_micros, start, &res);
+        res
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        let start = Instant::now();
+        let res = self.engine.get(key);
+        let counters = &self.counters;
+        self.record(&counters.gets, &counters.get_micros, start, &res);
+        res
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        let start = Instant::now();
+        let res = self.engine.remove(key);
+        let counters = &self.counters;
+        self.record(&counters.removes, &counters.remove_micros, start, &res);
+        res
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        let start = Instant::now();
+        let res = self.engine.scan(prefix);
+        let counters = &self.counters;
+        self.record(&counters.scans, &counters.scan_micros, start, &res);
+        res
+    }
+
+    fn scan_from(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<Vec<KVPair>> {
+        let start = Instant::now();
+        let res = self.engine.scan_from(prefix, after, limit);
+        let counters = &self.counters;
+        self.record(&counters.scans, &counters.scan_micros, start, &res);
+        res
+    }
+
+    fn stats(&self) -> HashMap<String, u64> {
+        let mut stats = self.engine.stats();
+        let counters = &self.counters;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        stats.insert("op_gets".to_owned(), load(&counters.gets));
+        stats.insert("op_sets".to_owned(), load(&counters.sets));
+        stats.insert("op_removes".to_owned(), load(&counters.removes));
+        stats.insert("op_scans".to_owned(), load(&counters.scans));
+        stats.insert("op_errors".to_owned(), load(&counters.errors));
+        stats.insert("op_get_micros".to_owned(), load(&counters.get_micros));
+        stats.insert("op_set_micros".to_owned(), load(&counters.set_micros));
+        stats.insert("op_remove_micros".to_owned(), load(&counters.remove_micros));
+        stats.insert("op_scan_micros".to_owned(), load(&counters.scan_micros));
+        stats
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.engine.flush()
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::common_struct::KVPair;
+use crate::engines::KVEngine;
+use crate::error::{KVError, Result};
+
+pub use self::cache::{CacheStats, KVCache};
+pub use self::metrics::KVMetrics;
+pub use self::prefix::KVPrefix;
+pub use self::validate::KVValidate;
+
+mod cache;
+mod metrics;
+mod prefix;
+mod validate;
+
+const DEFAULT_MAX_KEY_LEN: usize = 256;
+const DEFAULT_MAX_VAL_LEN: usize = 1 << 20;
+
+/// One layer of an engine stack, as given on the command line, e.g.
+/// `cache=1000`, `metrics`, `prefix=app1:` or `validate=256,4096`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerConfig {
+    Cache(usize),
+    Metrics,
+    Prefix(String),
+    Validate { max_key_len: usize, max_val_len: usize },
+}
+
+impl FromStr for LayerConfig {
+    type Err = KVError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut split = s.splitn(2, '=');
+        let name = split.next().unwrap_or_default();
+        let arg = split.next();
+        let invalid = || KVError::InvalidLayer(s.to_owned());
+
+        match (name, arg) {
+            ("cache", Some(size)) => size.parse().map(LayerConfig::Cache).map_err(|_| invalid()),
+            ("metrics", None) => Ok(LayerConfig::Metrics),
+            ("prefix", Some(prefix)) if !prefix.is_empty() => {
+                Ok(LayerConfig::Prefix(prefix.to_owned()))
+            }
+            ("validate", None) => Ok(LayerConfig::Validate {
+                max_key_len: DEFAULT_MAX_KEY_LEN,
+                max_val_len: DEFAULT_MAX_VAL_LEN,
+            }),
+            ("validate", Some(limits)) => {
+                let limits: Vec<&str> = limits.split(',').collect();
+                if limits.len() != 2 {
+                    return Err(invalid());
+                }
+                Ok(LayerConfig::Validate {
+                    max_key_len: limits[0].parse().map_err(|_| invalid())?,
+                    max_val_len: limits[1].parse().map_err(|_| invalid())?,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Wraps `engine` with `layers`, the first layer being the outermost one:
+/// `[Metrics, Cache(100)]` gives `KVMetrics<KVCache<E>>`.
+pub fn build_stack<E>(engine: E, layers: &[LayerConfig]) -> BoxedKVEngine
+where
+    E: KVEngine + Send + 'static,
+{
+    layers
+        .iter()
+        .rev()
+        .fold(BoxedKVEngine::new(engine), |engine, layer| match layer {
+            LayerConfig::Cache(size) => BoxedKVEngine::new(KVCache::new(engine, *size)),
+            LayerConfig::Metrics => BoxedKVEngine::new(KVMetrics::new(engine)),
+            LayerConfig::Prefix(prefix) => {
+                BoxedKVEngine::new(KVPrefix::new(engine, prefix.to_owned()))
+            }
+            LayerConfig::Validate {
+                max_key_len,
+                max_val_len,
+            } => BoxedKVEngine::new(KVValidate::new(engine, *max_key_len, *max_val_len)),
+        })
+}
+
+/// Type-erased `KVEngine`, used when the stack is only known at runtime.
+pub struct BoxedKVEngine(Box<dyn DynKVEngine>);
+
+impl BoxedKVEngine {
+    pub fn new<E: KVEngine + Send + 'static>(engine: E) -> Self {
+        BoxedKVEngine(Box::new(engine))
+    }
+}
+
+impl Clone for BoxedKVEngine {
+    fn clone(&self) -> Self {
+        BoxedKVEngine(self.0.box_clone())
+    }
+}
+
+impl KVEngine for BoxedKVEngine {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.0.dyn_set(key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.0.dyn_get(key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.0.dyn_remove(key)
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        self.0.dyn_scan(prefix)
+    }
+
+    fn scan_from(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<Vec<KVPair>> {
+        self.0.dyn_scan_from(prefix, after, limit)
+    }
+
+    fn stats(&self) -> HashMap<String, u64> {
+        self.0.dyn_stats()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.0.dyn_flush()
+    }
+}
+
+// `KVEngine` requires `Clone`, so it is not object safe: this mirror trait is.
+trait DynKVEngine: Send {
+    fn dyn_set(&self, key: String, value: String) -> Result<()>;
+
+    fn dyn_get(&self, key: String) -> Result<Option<String>>;
+
+    fn dyn_remove(&self, key: String) -> Result<()>;
+
+    fn dyn_scan(&self, prefix: &str) -> Result<Vec<KVPair>>;
+
+    fn dyn_scan_from(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<KVPair>>;
+
+    fn dyn_stats(&self) -> HashMap<String, u64>;
+
+    fn dyn_flush(&self) -> Result<()>;
+
+    fn box_clone(&self) -> Box<dyn DynKVEngine>;
+}
+
+impl<E: KVEngine + Send + 'static> DynKVEngine for E {
+    fn dyn_set(&self, key: String, value: String) -> Result<()> {
+        self.set(key, value)
+    }
+
+    fn dyn_get(&self, key: String) -> Result<Option<String>> {
+        self.get(key)
+    }
+
+    fn dyn_remove(&self, key: String) -> Result<()> {
+        self.remove(key)
+    }
+
+    fn dyn_scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        self.scan(prefix)
+    }
+
+    fn dyn_scan_from(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<KVPair>> {
+        self.scan_from(prefix, after, limit)
+    }
+
+    fn dyn_stats(&self) -> HashMap<String, u64> {
+        self.stats()
+    }
+
+    fn dyn_flush(&self) -> Result<()> {
+        self.flush()
+    }
+
+    fn box_clone(&self) -> Box<dyn DynKVEngine> {
+        Box::new(self.clone())
+    }
+}
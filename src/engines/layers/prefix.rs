@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+use crate::common_struct::KVPair;
+use crate::engines::KVEngine;
+use crate::error::Result;
+
+/// Namespaces every key of the wrapped engine under `prefix`.
+#[derive(Clone)]
+pub struct KVPrefix<E: KVEngine> {
+    engine: E,
+    prefix: String,
+}
+
+impl<E: KVEngine> KVPrefix<E> {
+    pub fn new(engine: E, prefix: String) -> Self {
+        KVPrefix { engine, prefix }
+    }
+
+    fn prefixed(&self, key: String) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+impl<E: KVEngine> KVEngine for KVPrefix<E> {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.engine.set(self.prefixed(key), value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.engine.get(self.prefixed(key))
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.engine.remove(self.prefixed(key))
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        let pairs = self.engine.scan(&self.prefixed(prefix.to_owned()))?;
+        Ok(pairs
+            .into_iter()
+            .map(|pair| KVPair::new(pair.key[self.prefix.len()..].to_owned(), pair.val))
+            .collect())
+    }
+
+    fn scan_from(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<Vec<KVPair>> {
+        let prefix = self.prefixed(prefix.to_owned());
+        let after = after.map(|after| self.prefixed(after.to_owned()));
+        let pairs = self.engine.scan_from(&prefix, after.as_deref(), limit)?;
+        Ok(pairs
+            .into_iter()
+            .map(|pair| KVPair::new(pair.key[self.prefix.len()..].to_owned(), pair.val))
+            .collect())
+    }
+
+    fn stats(&self) -> HashMap<String, u64> {
+        self.engine.stats()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.engine.flush()
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use crate::common_struct::KVPair;
+use crate::engines::KVEngine;
+use crate::error::{KVError, Result};
+
+/// Rejects empty or oversized keys and oversized values before they reach
+/// the wrapped engine.
+#[derive(Clone)]
+pub struct KVValidate<E: KVEngine> {
+    engine: E,
+    max_key_len: usize,
+    max_val_len: usize,
+}
+
+impl<E: KVEngine> KVValidate<E> {
+    pub fn new(engine: E, max_key_len: usize, max_val_len: usize) -> Self {
+        KVValidate {
+            engine,
+            max_key_len,
+            max_val_len,
+        }
+    }
+
+    fn check_key(&self, key: &str) -> Result<()> {
+        if key.is_empty() || key.len() > self.max_key_len {
+            return Err(KVError::InvalidKey(format!(
+                "key length must be between 1 and {} bytes",
+                self.max_key_len
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl<E: KVEngine> KVEngine for KVValidate<E> {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.check_key(&key)?;
+        if value.len() > self.max_val_len {
+            return Err(KVError::InvalidValue(format!(
+                "value length must not exceed {} bytes",
+                self.max_val_len
+            )));
+        }
+        self.engine.set(key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.check_key(&key)?;
+        self.engine.get(key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.check_key(&key)?;
+        self.engine.remove(key)
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        self.engine.scan(prefix)
+    }
+
+    fn scan_from(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<Vec<KVPair>> {
+        self.engine.scan_from(prefix, after, limit)
+    }
+
+    fn stats(&self) -> HashMap<String, u64> {
+        self.engine.stats()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.engine.flush()
+    }
+}
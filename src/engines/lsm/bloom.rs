@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Bloom filter persisted in every SSTable.
+///
+/// Hashing is a hand-rolled FNV-1a rather than `DefaultHasher`, whose output
+/// is not guaranteed to stay the same across Rust releases.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(num_keys: usize, bits_per_key: usize) -> Self {
+        let num_bits = (num_keys * bits_per_key).max(64);
+        // optimal number of hash functions is bits_per_key * ln(2)
+        let num_hashes = ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30);
+        BloomFilter {
+            bits: vec![0; (num_bits + 63) / 64],
+            num_hashes,
+        }
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        let num_bits = self.num_bits();
+        let (h1, h2) = hash_pair(key);
+        for i in 0..u64::from(self.num_hashes) {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % num_bits;
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// `false` means the key is certainly absent.
+    pub fn may_contain(&self, key: &str) -> bool {
+        let num_bits = self.num_bits();
+        let (h1, h2) = hash_pair(key);
+        (0..u64::from(self.num_hashes)).all(|i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % num_bits;
+            self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+        })
+    }
+
+    fn num_bits(&self) -> u64 {
+        self.bits.len() as u64 * 64
+    }
+}
+
+fn hash_pair(key: &str) -> (u64, u64) {
+    let h1 = key.bytes().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    });
+    // second hash for double hashing, forced odd so every bit is reachable
+    let h2 = (h1.rotate_left(31) ^ FNV_OFFSET).wrapping_mul(FNV_PRIME) | 1;
+    (h1, h2)
+}
@@ -0,0 +1,466 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::iter::Peekable;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use self::sstable::SSTable;
+use crate::common_struct::KVPair;
+use crate::engines::meta::EngineMeta;
+use crate::engines::wal::Wal;
+use crate::engines::{EngineOptions, KVEngine};
+use crate::error::{KVError, Result};
+use crate::lock::DirLock;
+
+mod bloom;
+mod sstable;
+
+const ENGINE_NAME: &str = "lsm";
+const FORMAT_VERSION: u32 = 1;
+
+const LSM_DIR: &str = "lsm";
+const WAL_FILE: &str = "wal.log";
+const MANIFEST_FILE: &str = "MANIFEST";
+
+const TABLE_TARGET_SIZE: usize = 2 << 20;
+const L1_MAX_SIZE: u64 = 10 << 20;
+const LEVEL_SIZE_MULTIPLIER: u64 = 10;
+const MAX_LEVELS: usize = 7;
+
+/// Record stored in the WAL and in SSTables, `val: None` being a tombstone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    pub val: Option<String>,
+}
+
+/// LSM-tree engine: memtable + write-ahead log, flushed to sorted SSTables
+/// with block indexes and bloom filters, merged by leveled compaction.
+#[derive(Clone)]
+pub struct LsmKVEngine(Arc<RwLock<LsmTree>>);
+
+impl LsmKVEngine {
+    pub fn open(dir_path: &Path) -> Result<Self> {
+        LsmKVEngine::open_with(dir_path, &EngineOptions::default())
+    }
+
+    pub fn open_with(dir_path: &Path, options: &EngineOptions) -> Result<Self> {
+        let lock = DirLock::exclusive(dir_path)?;
+        let mut params = BTreeMap::new();
+        params.insert("memtable_limit".to_owned(), options.memtable_limit.to_string());
+        params.insert("table_target_size".to_owned(), TABLE_TARGET_SIZE.to_string());
+        params.insert("max_levels".to_owned(), MAX_LEVELS.to_string());
+        EngineMeta::open(dir_path, ENGINE_NAME, FORMAT_VERSION, params)?;
+
+        let tree = LsmTree::open(&dir_path.join(LSM_DIR), options, lock)?;
+        Ok(LsmKVEngine(Arc::new(RwLock::new(tree))))
+    }
+}
+
+impl KVEngine for LsmKVEngine {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.0.write().unwrap().write(Entry {
+            key,
+            val: Some(value),
+        })
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.0.read().unwrap().get(&key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        let mut tree = self.0.write().unwrap();
+        if tree.get(&key)?.is_none() {
+            return Err(KVError::FailGet(key));
+        }
+        tree.write(Entry { key, val: None })
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        self.0.read().unwrap().scan(prefix)
+    }
+
+    fn scan_from(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<Vec<KVPair>> {
+        self.0.read().unwrap().scan_from(prefix, after, limit)
+    }
+
+    fn stats(&self) -> HashMap<String, u64> {
+        let tree = self.0.read().unwrap();
+        let mut stats = HashMap::new();
+        stats.insert("memtable_keys".to_owned(), tree.memtable.len() as u64);
+        for (level, tables) in tree.levels.iter().enumerate() {
+            stats.insert(format!("l{}_tables", level), tables.len() as u64);
+        }
+        stats
+    }
+
+    fn flush(&self) -> Result<()> {
+        // tables are synced when written, the memtable lives in the WAL
+        self.0.write().unwrap().wal.sync()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    next_id: u64,
+    levels: Vec<Vec<u64>>,
+}
+
+struct LsmTree {
+    dir: PathBuf,
+    memtable: BTreeMap<String, Option<String>>,
+    memtable_size: usize,
+    wal: Wal<Entry>,
+    // level 0 tables may overlap and are ordered oldest first, deeper levels
+    // hold disjoint tables ordered by first key
+    levels: Vec<Vec<Arc<SSTable>>>,
+    next_id: u64,
+    memtable_limit: usize,
+    l0_compaction_trigger: usize,
+    _lock: DirLock,
+}
+
+impl LsmTree {
+    fn open(dir: &Path, options: &EngineOptions, lock: DirLock) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let manifest: Manifest = if manifest_path.exists() {
+            serde_json::from_reader(File::open(&manifest_path)?)?
+        } else {
+            Manifest::default()
+        };
+
+        let mut levels = vec![Vec::new(); MAX_LEVELS];
+        for (level, ids) in manifest.levels.iter().enumerate().take(MAX_LEVELS) {
+            for id in ids {
+                levels[level].push(Arc::new(SSTable::open(dir, *id)?));
+            }
+        }
+
+        let wal_path = dir.join(WAL_FILE);
+        let mut tree = LsmTree {
+            dir: dir.to_path_buf(),
+            memtable: BTreeMap::new(),
+            memtable_size: 0,
+            wal: Wal::open(&wal_path, options.sync)?,
+            levels,
+            next_id: manifest.next_id,
+            memtable_limit: options.memtable_limit,
+            l0_compaction_trigger: options.l0_compaction_trigger,
+            _lock: lock,
+        };
+        for entry in Wal::<Entry>::replay(&wal_path)? {
+            tree.insert_memtable(entry);
+        }
+        tree.remove_orphan_tables()?;
+        // replay stops at a torn record, which would hide the records
+        // appended after it, so the log starts over once its records are in
+        // a table
+        tree.flush_memtable()?;
+        tree.wal.truncate()?;
+
+        Ok(tree)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        if let Some(val) = self.memtable.get(key) {
+            return Ok(val.to_owned());
+        }
+
+        for table in self.levels[0].iter().rev() {
+            if let Some(val) = table.get(key)? {
+                return Ok(val);
+            }
+        }
+
+        for tables in &self.levels[1..] {
+            if let Some(table) = tables.iter().find(|table| table.covers(key)) {
+                if let Some(val) = table.get(key)? {
+                    return Ok(val);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        // oldest data first so newer entries overwrite it: deepest level
+        // first, then level 0 from oldest to newest, then the memtable
+        let mut merged = BTreeMap::new();
+        let tables = self.levels[1..]
+            .iter()
+            .rev()
+            .flatten()
+            .chain(self.levels[0].iter())
+            .filter(|table| table.may_hold_prefix(prefix));
+        for table in tables {
+            for entry in table.entries()? {
+                if entry.key.starts_with(prefix) {
+                    merged.insert(entry.key, entry.val);
+                }
+            }
+        }
+        for (key, val) in self.memtable.range(prefix.to_owned()..) {
+            if !key.starts_with(prefix) {
+                break;
+            }
+            merged.insert(key.to_owned(), val.to_owned());
+        }
+
+        Ok(merged
+            .into_iter()
+            .filter_map(|(key, val)| val.map(|val| KVPair::new(key, val)))
+            .collect())
+    }
+
+    /// Merges the memtable and the tables from the first key to answer on,
+    /// reading only the blocks the page needs.
+    fn scan_from(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<Vec<KVPair>> {
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after.to_owned()),
+            _ => Bound::Included(prefix.to_owned()),
+        };
+        let wanted = |table: &&Arc<SSTable>| {
+            table.may_hold_prefix(prefix) && after.map_or(true, |after| *table.last_key > *after)
+        };
+
+        // newest first: the memtable, level 0 from newest to oldest, then
+        // each deeper level, whose disjoint tables are read one after another
+        let memtable = self
+            .memtable
+            .range((start.clone(), Bound::Unbounded))
+            .map(|(key, val)| {
+                Ok(Entry {
+                    key: key.to_owned(),
+                    val: val.to_owned(),
+                })
+            });
+        let mut sources = vec![source(memtable)];
+        for table in self.levels[0].iter().rev().filter(wanted) {
+            sources.push(source(table.entries_from(&start)));
+        }
+        for tables in &self.levels[1..] {
+            let entries = tables.iter().filter(wanted).flat_map(|table| table.entries_from(&start));
+            sources.push(source(entries));
+        }
+
+        let mut pairs = Vec::new();
+        while pairs.len() < limit {
+            // smallest key of the sources, the newest of them holding it
+            let mut next: Option<(usize, String)> = None;
+            for (i, source) in sources.iter_mut().enumerate() {
+                if let Some(Err(_)) = source.peek() {
+                    return Err(source.next().unwrap().unwrap_err());
+                }
+                if let Some(Ok(entry)) = source.peek() {
+                    if next.as_ref().map_or(true, |(_, key)| entry.key < *key) {
+                        next = Some((i, entry.key.to_owned()));
+                    }
+                }
+            }
+            let (newest, key) = match next {
+                Some(next) if next.1.starts_with(prefix) => next,
+                _ => break,
+            };
+
+            // every source moves past the key, the newest one answers it
+            let mut val = None;
+            for (i, source) in sources.iter_mut().enumerate() {
+                if let Some(Ok(entry)) = source.peek() {
+                    if entry.key == key {
+                        let entry = source.next().unwrap()?;
+                        if i == newest {
+                            val = entry.val;
+                        }
+                    }
+                }
+            }
+            if let Some(val) = val {
+                pairs.push(KVPair::new(key, val));
+            }
+        }
+        Ok(pairs)
+    }
+
+    fn write(&mut self, entry: Entry) -> Result<()> {
+        self.wal.append(&entry)?;
+        self.insert_memtable(entry);
+
+        if self.memtable_size >= self.memtable_limit {
+            self.flush_memtable()?;
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn insert_memtable(&mut self, entry: Entry) {
+        self.memtable_size += entry.key.len() + entry.val.as_ref().map_or(0, String::len);
+        self.memtable.insert(entry.key, entry.val);
+    }
+
+    fn flush_memtable(&mut self) -> Result<()> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+
+        let entries: Vec<Entry> = self
+            .memtable
+            .iter()
+            .map(|(key, val)| Entry {
+                key: key.to_owned(),
+                val: val.to_owned(),
+            })
+            .collect();
+        let id = self.alloc_id();
+        let table = SSTable::create(&self.dir, id, &entries)?;
+        self.levels[0].push(Arc::new(table));
+        self.save_manifest()?;
+
+        self.memtable.clear();
+        self.memtable_size = 0;
+        self.wal.truncate()?;
+        Ok(())
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        loop {
+            if self.levels[0].len() >= self.l0_compaction_trigger {
+                let inputs: Vec<Arc<SSTable>> = self.levels[0].clone();
+                self.compact_into(0, inputs)?;
+                continue;
+            }
+
+            let full_level = (1..MAX_LEVELS - 1).find(|level| {
+                let size: u64 = self.levels[*level].iter().map(|table| table.size).sum();
+                size > max_level_size(*level)
+            });
+            match full_level {
+                Some(level) => {
+                    let input = Arc::clone(&self.levels[level][0]);
+                    self.compact_into(level, vec![input])?;
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Merges `inputs` from `level` with the overlapping tables of the next
+    /// level, writing the result to the next level.
+    fn compact_into(&mut self, level: usize, inputs: Vec<Arc<SSTable>>) -> Result<()> {
+        let first_key = inputs.iter().map(|t| t.first_key.as_str()).min().unwrap_or("");
+        let last_key = inputs.iter().map(|t| t.last_key.as_str()).max().unwrap_or("");
+        let overlapping: Vec<Arc<SSTable>> = self.levels[level + 1]
+            .iter()
+            .filter(|table| table.overlaps(first_key, last_key))
+            .cloned()
+            .collect();
+
+        // older data first so newer entries overwrite it
+        let mut merged = BTreeMap::new();
+        for table in overlapping.iter().chain(inputs.iter()) {
+            for entry in table.entries()? {
+                merged.insert(entry.key, entry.val);
+            }
+        }
+
+        // tombstones can be dropped once nothing older lies below
+        let is_bottom = self.levels[level + 2..].iter().all(Vec::is_empty);
+
+        let mut outputs = Vec::new();
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        for (key, val) in merged {
+            if is_bottom && val.is_none() {
+                continue;
+            }
+            batch_size += key.len() + val.as_ref().map_or(0, String::len);
+            batch.push(Entry { key, val });
+            if batch_size >= TABLE_TARGET_SIZE {
+                let id = self.alloc_id();
+                outputs.push(Arc::new(SSTable::create(&self.dir, id, &batch)?));
+                batch.clear();
+                batch_size = 0;
+            }
+        }
+        if !batch.is_empty() {
+            let id = self.alloc_id();
+            outputs.push(Arc::new(SSTable::create(&self.dir, id, &batch)?));
+        }
+
+        let is_input = |table: &Arc<SSTable>| {
+            inputs.iter().chain(overlapping.iter()).any(|t| t.id == table.id)
+        };
+        self.levels[level].retain(|table| !is_input(table));
+        self.levels[level + 1].retain(|table| !is_input(table));
+        self.levels[level + 1].extend(outputs);
+        self.levels[level + 1].sort_by(|a, b| a.first_key.cmp(&b.first_key));
+        self.save_manifest()?;
+
+        for table in inputs.iter().chain(overlapping.iter()) {
+            table.delete()?;
+        }
+        Ok(())
+    }
+
+    fn alloc_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn save_manifest(&self) -> Result<()> {
+        let manifest = Manifest {
+            next_id: self.next_id,
+            levels: self
+                .levels
+                .iter()
+                .map(|tables| tables.iter().map(|table| table.id).collect())
+                .collect(),
+        };
+
+        let tmp_path = self.dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &manifest)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+
+    /// Deletes tables left behind by a crash between writing a table and
+    /// recording it in the manifest.
+    fn remove_orphan_tables(&self) -> Result<()> {
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            let is_sst = path.extension().and_then(|ext| ext.to_str()) == Some("sst");
+            let id = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok());
+            if let (true, Some(id)) = (is_sst, id) {
+                let live = self.levels.iter().flatten().any(|table| table.id == id);
+                if !live {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Entries of a memtable or of tables, in key order.
+type Source<'a> = Peekable<Box<dyn Iterator<Item = Result<Entry>> + 'a>>;
+
+fn source<'a, I: Iterator<Item = Result<Entry>> + 'a>(entries: I) -> Source<'a> {
+    let entries: Box<dyn Iterator<Item = Result<Entry>> + 'a> = Box::new(entries);
+    entries.peekable()
+}
+
+fn max_level_size(level: usize) -> u64 {
+    L1_MAX_SIZE * LEVEL_SIZE_MULTIPLIER.pow(level as u32 - 1)
+}
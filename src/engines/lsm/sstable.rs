@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::engines::lsm::bloom::BloomFilter;
+use crate::engines::lsm::Entry;
+use crate::error::{KVError, Result};
+
+const BLOCK_SIZE: usize = 4096;
+const BLOOM_BITS_PER_KEY: usize = 10;
+const FOOTER_LEN: u64 = 8;
+
+/// Immutable sorted table.
+///
+/// Layout: data blocks of newline separated JSON entries, then a JSON
+/// metadata section (block index, bloom filter, key range), then an 8-byte
+/// big-endian footer holding the offset of the metadata section.
+pub struct SSTable {
+    pub id: u64,
+    pub size: u64,
+    pub first_key: String,
+    pub last_key: String,
+    path: PathBuf,
+    index: Vec<BlockHandle>,
+    bloom: BloomFilter,
+    file: Mutex<File>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BlockHandle {
+    first_key: String,
+    offset: u64,
+    len: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TableMeta {
+    first_key: String,
+    last_key: String,
+    index: Vec<BlockHandle>,
+    bloom: BloomFilter,
+}
+
+impl SSTable {
+    pub fn file_name(id: u64) -> String {
+        format!("{:08}.sst", id)
+    }
+
+    /// Writes `entries`, which must be sorted by key and not empty.
+    pub fn create(dir: &Path, id: u64, entries: &[Entry]) -> Result<SSTable> {
+        let (first, last) = match (entries.first(), entries.last()) {
+            (Some(first), Some(last)) => (first.key.to_owned(), last.key.to_owned()),
+            _ => return Err(KVError::StringError("empty sstable".to_owned())),
+        };
+
+        let path = dir.join(SSTable::file_name(id));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let mut bloom = BloomFilter::new(entries.len(), BLOOM_BITS_PER_KEY);
+        let mut index = Vec::new();
+        let mut block = Vec::with_capacity(BLOCK_SIZE);
+        let mut block_first_key = String::new();
+        let mut offset = 0;
+
+        for entry in entries {
+            if block.is_empty() {
+                block_first_key = entry.key.to_owned();
+            }
+            bloom.insert(&entry.key);
+            serde_json::to_writer(&mut block, entry)?;
+            block.push(b'\n');
+
+            if block.len() >= BLOCK_SIZE {
+                writer.write_all(&block)?;
+                index.push(BlockHandle {
+                    first_key: block_first_key.to_owned(),
+                    offset,
+                    len: block.len() as u64,
+                });
+                offset += block.len() as u64;
+                block.clear();
+            }
+        }
+        if !block.is_empty() {
+            writer.write_all(&block)?;
+            index.push(BlockHandle {
+                first_key: block_first_key,
+                offset,
+                len: block.len() as u64,
+            });
+            offset += block.len() as u64;
+        }
+
+        let meta = TableMeta {
+            first_key: first,
+            last_key: last,
+            index,
+            bloom,
+        };
+        serde_json::to_writer(&mut writer, &meta)?;
+        writer.write_all(&offset.to_be_bytes())?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+
+        SSTable::open(dir, id)
+    }
+
+    pub fn open(dir: &Path, id: u64) -> Result<SSTable> {
+        let path = dir.join(SSTable::file_name(id));
+        let mut file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        if size < FOOTER_LEN {
+            return Err(KVError::StringError(format!("corrupted sstable {:?}", path)));
+        }
+
+        let mut footer = [0; FOOTER_LEN as usize];
+        file.seek(SeekFrom::Start(size - FOOTER_LEN))?;
+        file.read_exact(&mut footer)?;
+        let meta_offset = u64::from_be_bytes(footer);
+        let meta_len = (size - FOOTER_LEN)
+            .checked_sub(meta_offset)
+            .ok_or_else(|| KVError::StringError(format!("corrupted sstable {:?}", path)))?;
+
+        let mut meta_buf = vec![0; meta_len as usize];
+        file.seek(SeekFrom::Start(meta_offset))?;
+        file.read_exact(&mut meta_buf)?;
+        let meta: TableMeta = serde_json::from_slice(&meta_buf)?;
+
+        Ok(SSTable {
+            id,
+            size,
+            first_key: meta.first_key,
+            last_key: meta.last_key,
+            path,
+            index: meta.index,
+            bloom: meta.bloom,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn covers(&self, key: &str) -> bool {
+        self.first_key.as_str() <= key && key <= self.last_key.as_str()
+    }
+
+    pub fn may_hold_prefix(&self, prefix: &str) -> bool {
+        self.last_key.as_str() >= prefix
+            && (self.first_key.starts_with(prefix) || self.first_key.as_str() < prefix)
+    }
+
+    pub fn overlaps(&self, first_key: &str, last_key: &str) -> bool {
+        self.first_key.as_str() <= last_key && first_key <= self.last_key.as_str()
+    }
+
+    /// `Some(None)` is a tombstone, `None` means the table knows nothing
+    /// about `key`.
+    pub fn get(&self, key: &str) -> Result<Option<Option<String>>> {
+        if !self.covers(key) || !self.bloom.may_contain(key) {
+            return Ok(None);
+        }
+
+        // last block starting at or before `key`
+        let block_idx = match self
+            .index
+            .binary_search_by(|handle| handle.first_key.as_str().cmp(key))
+        {
+            Ok(idx) => idx,
+            Err(0) => return Ok(None),
+            Err(idx) => idx - 1,
+        };
+
+        let entry = self
+            .read_block(&self.index[block_idx])?
+            .into_iter()
+            .find(|entry| entry.key == key);
+        Ok(entry.map(|entry| entry.val))
+    }
+
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for handle in &self.index {
+            entries.extend(self.read_block(handle)?);
+        }
+        Ok(entries)
+    }
+
+    /// Entries from `start` on, in key order, reading blocks as they are
+    /// needed.
+    pub fn entries_from<'a>(
+        &'a self,
+        start: &'a Bound<String>,
+    ) -> impl Iterator<Item = Result<Entry>> + 'a {
+        // last block starting at or before the start key
+        let first_block = match start {
+            Bound::Included(key) | Bound::Excluded(key) => match self
+                .index
+                .binary_search_by(|handle| handle.first_key.as_str().cmp(key))
+            {
+                Ok(idx) => idx,
+                Err(0) => 0,
+                Err(idx) => idx - 1,
+            },
+            Bound::Unbounded => 0,
+        };
+
+        self.index[first_block..]
+            .iter()
+            .flat_map(move |handle| match self.read_block(handle) {
+                Ok(entries) => entries.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            })
+            .filter(move |entry| match (entry, start) {
+                (Ok(entry), Bound::Included(key)) => entry.key >= *key,
+                (Ok(entry), Bound::Excluded(key)) => entry.key > *key,
+                _ => true,
+            })
+    }
+
+    pub fn delete(&self) -> Result<()> {
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+
+    fn read_block(&self, handle: &BlockHandle) -> Result<Vec<Entry>> {
+        let mut buf = vec![0; handle.len as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(handle.offset))?;
+            file.read_exact(&mut buf)?;
+        }
+
+        buf.split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| Ok(serde_json::from_slice(line)?))
+            .collect()
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::common_struct::KVPair;
+use crate::engines::meta::EngineMeta;
+use crate::engines::KVEngine;
+use crate::error::{KVError, Result};
+use crate::lock::DirLock;
+
+const ENGINE_NAME: &str = "memory";
+const FORMAT_VERSION: u32 = 1;
+
+const DUMP_FILE: &str = "memory_dump.json";
+
+/// Pure in-memory engine, optionally dumped to disk at a fixed interval.
+#[derive(Clone)]
+pub struct MemoryKVEngine {
+    map: Arc<RwLock<HashMap<String, String>>>,
+    dump_path: Option<Arc<PathBuf>>,
+    _lock: Option<Arc<DirLock>>,
+}
+
+impl MemoryKVEngine {
+    pub fn new() -> Self {
+        MemoryKVEngine {
+            map: Arc::new(RwLock::new(HashMap::new())),
+            dump_path: None,
+            _lock: None,
+        }
+    }
+
+    /// Loads the last dump found in `dir_path`, then dumps the whole map
+    /// there every `interval` until every clone of the engine is dropped.
+    pub fn open_with_dump(dir_path: &Path, interval: Duration) -> Result<Self> {
+        let lock = DirLock::exclusive(dir_path)?;
+        EngineMeta::open(dir_path, ENGINE_NAME, FORMAT_VERSION, BTreeMap::new())?;
+
+        let dump_path = dir_path.join(DUMP_FILE);
+        let map = if dump_path.exists() {
+            let reader = BufReader::new(File::open(&dump_path)?);
+            serde_json::from_reader(reader)?
+        } else {
+            HashMap::new()
+        };
+
+        let engine = MemoryKVEngine {
+            map: Arc::new(RwLock::new(map)),
+            dump_path: Some(Arc::new(dump_path.to_owned())),
+            _lock: Some(Arc::new(lock)),
+        };
+
+        let map = Arc::downgrade(&engine.map);
+        thread::spawn(move || dump_loop(map, dump_path, interval));
+
+        Ok(engine)
+    }
+
+    pub fn dump(&self, dump_path: &Path) -> Result<()> {
+        dump_map(&self.map, dump_path)
+    }
+}
+
+impl Default for MemoryKVEngine {
+    fn default() -> Self {
+        MemoryKVEngine::new()
+    }
+}
+
+impl KVEngine for MemoryKVEngine {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.map.write().unwrap().insert(key, value);
+        Ok(())
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        Ok(self.map.read().unwrap().get(&key).cloned())
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.map
+            .write()
+            .unwrap()
+            .remove(&key)
+            .ok_or(KVError::FailGet(key))?;
+        Ok(())
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        let mut pairs: Vec<KVPair> = self
+            .map
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, val)| KVPair::new(key.to_owned(), val.to_owned()))
+            .collect();
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(pairs)
+    }
+
+    fn stats(&self) -> HashMap<String, u64> {
+        let mut stats = HashMap::new();
+        stats.insert("keys".to_owned(), self.map.read().unwrap().len() as u64);
+        stats
+    }
+
+    fn flush(&self) -> Result<()> {
+        match &self.dump_path {
+            Some(dump_path) => self.dump(dump_path),
+            None => Ok(()),
+        }
+    }
+}
+
+fn dump_loop(map: Weak<RwLock<HashMap<String, String>>>, dump_path: PathBuf, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        match map.upgrade() {
+            Some(map) => {
+                // a failed dump is retried at the next tick
+                let _ = dump_map(&map, &dump_path);
+            }
+            None => return,
+        }
+    }
+}
+
+fn dump_map(map: &RwLock<HashMap<String, String>>, dump_path: &Path) -> Result<()> {
+    // writers only wait for the copy, not for the disk
+    let snapshot = map.read().unwrap().clone();
+    // write a temporary file first so a crash never leaves a truncated dump
+    let tmp_path = dump_path.with_extension("tmp");
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &snapshot)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+    }
+    fs::rename(&tmp_path, dump_path)?;
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::error::{KVError, Result};
+
+pub const META_FILE: &str = "kvs_meta.json";
+
+// files laid out by engines released before the metadata file existed
+const LEGACY_FILES: &[(&str, &str)] = &[
+    ("log_file.txt", "kvs"),
+    ("my_old_db", "sled"),
+    ("lsm", "lsm"),
+    ("btree", "btree"),
+];
+
+/// Versioned manifest stored at the root of every data directory, recording
+/// which engine owns the directory and how it was created.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EngineMeta {
+    pub engine: String,
+    pub format_version: u32,
+    pub created_by: String,
+    pub params: BTreeMap<String, String>,
+}
+
+impl EngineMeta {
+    pub fn new(engine: &str, format_version: u32, params: BTreeMap<String, String>) -> Self {
+        EngineMeta {
+            engine: engine.to_owned(),
+            format_version,
+            created_by: format!("kvs {}", env!("CARGO_PKG_VERSION")),
+            params,
+        }
+    }
+
+    /// Reads the metadata of `dir_path`, inferring it from the file layout
+    /// for directories created before the metadata file was introduced.
+    pub fn read(dir_path: &Path) -> Result<Option<EngineMeta>> {
+        let meta_path = dir_path.join(META_FILE);
+        if meta_path.exists() {
+            let reader = BufReader::new(File::open(meta_path)?);
+            return Ok(Some(serde_json::from_reader(reader)?));
+        }
+
+        let legacy = LEGACY_FILES
+            .iter()
+            .find(|(file, _)| dir_path.join(file).exists())
+            .map(|(_, engine)| EngineMeta::new(engine, 1, BTreeMap::new()));
+        Ok(legacy)
+    }
+
+    pub fn write(&self, dir_path: &Path) -> Result<()> {
+        fs::create_dir_all(dir_path)?;
+        let tmp_path = dir_path.join(format!("{}.tmp", META_FILE));
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer_pretty(&mut writer, self)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp_path, dir_path.join(META_FILE))?;
+        Ok(())
+    }
+
+    /// Validates that `dir_path` belongs to `engine` in a format no newer
+    /// than `format_version`, writing the metadata on first open.
+    pub fn open(
+        dir_path: &Path,
+        engine: &str,
+        format_version: u32,
+        params: BTreeMap<String, String>,
+    ) -> Result<EngineMeta> {
+        match EngineMeta::read(dir_path)? {
+            Some(meta) => {
+                meta.check(engine, format_version)?;
+                if !dir_path.join(META_FILE).exists() {
+                    meta.write(dir_path)?;
+                }
+                Ok(meta)
+            }
+            None => {
+                let meta = EngineMeta::new(engine, format_version, params);
+                meta.write(dir_path)?;
+                Ok(meta)
+            }
+        }
+    }
+
+    /// Same validation as `open`, without writing anything: the directory
+    /// must already hold data of `engine`.
+    pub fn open_read_only(
+        dir_path: &Path,
+        engine: &str,
+        format_version: u32,
+    ) -> Result<EngineMeta> {
+        match EngineMeta::read(dir_path)? {
+            Some(meta) => {
+                meta.check(engine, format_version)?;
+                Ok(meta)
+            }
+            None => Err(KVError::StringError(format!(
+                "no {} data found in {}",
+                engine,
+                dir_path.display()
+            ))),
+        }
+    }
+
+    fn check(&self, engine: &str, format_version: u32) -> Result<()> {
+        if self.engine != engine {
+            return Err(KVError::WrongEngine {
+                expected: engine.to_owned(),
+                found: self.engine.to_owned(),
+            });
+        }
+        if self.format_version > format_version {
+            return Err(KVError::UnsupportedFormat {
+                engine: self.engine.to_owned(),
+                version: self.format_version,
+            });
+        }
+        Ok(())
+    }
+}
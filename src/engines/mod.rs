@@ -1,10 +1,26 @@
-use crate::error::Result;
+use crate::common_struct::KVPair;
+use crate::error::{KVError, Result};
+use std::collections::HashMap;
+use std::path::Path;
 
+pub use self::btree::BTreeKVEngine;
 pub use self::kvstore::KVStore;
+pub use self::layers::{
+    build_stack, BoxedKVEngine, CacheStats, KVCache, KVMetrics, KVPrefix, KVValidate, LayerConfig,
+};
+pub use self::lsm::LsmKVEngine;
+pub use self::memory::MemoryKVEngine;
+pub use self::meta::EngineMeta;
 pub use self::sled_kvs::SledKVEngine;
 
+mod btree;
 mod kvstore;
+mod layers;
+mod lsm;
+mod memory;
+mod meta;
 mod sled_kvs;
+mod wal;
 
 pub trait KVEngine: Clone {
     fn set(&self, key: String, value: String) -> Result<()>;
@@ -12,4 +28,66 @@ pub trait KVEngine: Clone {
     fn get(&self, key: String) -> Result<Option<String>>;
 
     fn remove(&self, key: String) -> Result<()>;
+
+    /// Live pairs whose key starts with `prefix`, sorted by key. Engines
+    /// which cannot list their keys answer `KVError::Unsupported`.
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        let _ = prefix;
+        Err(KVError::Unsupported("scan".to_owned()))
+    }
+
+    /// Up to `limit` pairs of `scan(prefix)` whose key sorts after `after`,
+    /// so that a large scan can be read a page at a time. Engines able to
+    /// seek to a key override it, the default scanning the whole prefix.
+    fn scan_from(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<Vec<KVPair>> {
+        let pairs = self.scan(prefix)?;
+        Ok(pairs
+            .into_iter()
+            .filter(|pair| after.map_or(true, |after| pair.key.as_str() > after))
+            .take(limit)
+            .collect())
+    }
+
+    fn stats(&self) -> HashMap<String, u64> {
+        HashMap::new()
+    }
+
+    /// Syncs every acknowledged write to disk, called before shutting down.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Tuning knobs of the on-disk engines, each engine ignoring the ones it
+/// has no use for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineOptions {
+    /// Sync every write to disk before acknowledging it, instead of only
+    /// handing it to the OS.
+    pub sync: bool,
+    /// lsm: memtable size in bytes flushed to a level 0 table.
+    pub memtable_limit: usize,
+    /// lsm: number of level 0 tables merged into level 1 at once.
+    pub l0_compaction_trigger: usize,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            sync: false,
+            memtable_limit: 1 << 20,
+            l0_compaction_trigger: 4,
+        }
+    }
+}
+
+/// Opens the on-disk engine called `name` in `dir_path`.
+pub fn open_engine(name: &str, dir_path: &Path) -> Result<BoxedKVEngine> {
+    match name {
+        "kvs" => Ok(BoxedKVEngine::new(KVStore::open(dir_path)?)),
+        "sled" => Ok(BoxedKVEngine::new(SledKVEngine::open(dir_path)?)),
+        "lsm" => Ok(BoxedKVEngine::new(LsmKVEngine::open(dir_path)?)),
+        "btree" => Ok(BoxedKVEngine::new(BTreeKVEngine::open(dir_path)?)),
+        _ => Err(KVError::EngineNotFound(format!("unknown engine {}", name))),
+    }
 }
@@ -1,40 +1,87 @@
 use sled::Db;
+use std::collections::BTreeMap;
+use std::ops::Bound;
 use std::path::Path;
+use std::sync::Arc;
 
+use crate::common_struct::KVPair;
+use crate::engines::meta::EngineMeta;
 use crate::engines::KVEngine;
 use crate::error::{KVError, Result};
+use crate::lock::DirLock;
+
+const ENGINE_NAME: &str = "sled";
+const FORMAT_VERSION: u32 = 1;
+
+const DB_DIR: &str = "my_old_db";
 
 #[derive(Clone)]
-pub struct SledKVEngine(Db);
+pub struct SledKVEngine {
+    db: Db,
+    read_only: bool,
+    _lock: Arc<DirLock>,
+}
 
 impl SledKVEngine {
     pub fn open(dir_path: &Path) -> Result<Self> {
         match dir_path.to_str() {
             Some(dir_str) => {
-                let kvs_path = format!("{}{}", dir_str, "/log_file.txt");
-                if Path::new(&kvs_path).exists() {
-                    return Err(KVError::WrongEngine);
-                }
+                let lock = DirLock::exclusive(dir_path)?;
+                EngineMeta::open(dir_path, ENGINE_NAME, FORMAT_VERSION, BTreeMap::new())?;
 
-                let db_path = format!("{}{}", dir_str, "/my_old_db");
+                let db_path = format!("{}/{}", dir_str, DB_DIR);
                 let db = sled::open(db_path)?;
-                Ok(SledKVEngine(db))
+                Ok(SledKVEngine {
+                    db,
+                    read_only: false,
+                    _lock: Arc::new(lock),
+                })
             }
             None => Err(KVError::None),
         }
     }
+
+    /// Opens an existing database in sled's read-only mode, sharing the
+    /// directory with other read-only opens but not with a writer.
+    pub fn open_read_only(dir_path: &Path) -> Result<Self> {
+        let lock = DirLock::shared(dir_path)?;
+        EngineMeta::open_read_only(dir_path, ENGINE_NAME, FORMAT_VERSION)?;
+
+        let db_path = dir_path.join(DB_DIR);
+        if !db_path.is_dir() {
+            return Err(KVError::StringError(format!(
+                "no sled database found in {}",
+                dir_path.display()
+            )));
+        }
+        let db = sled::Config::new().path(db_path).read_only(true).open()?;
+        Ok(SledKVEngine {
+            db,
+            read_only: true,
+            _lock: Arc::new(lock),
+        })
+    }
+
+    fn writable(&self) -> Result<&Db> {
+        if self.read_only {
+            Err(KVError::ReadOnly)
+        } else {
+            Ok(&self.db)
+        }
+    }
 }
 
 impl KVEngine for SledKVEngine {
     fn set(&self, key: String, value: String) -> Result<()> {
-        self.0.insert(key, value.as_bytes()).map(|_| ())?;
-        self.0.flush()?;
+        let db = self.writable()?;
+        db.insert(key, value.as_bytes()).map(|_| ())?;
+        db.flush()?;
         Ok(())
     }
 
     fn get(&self, key: String) -> Result<Option<String>> {
         Ok(self
-            .0
+            .db
             .get(key)?
             .map(|i_vec| AsRef::<[u8]>::as_ref(&i_vec).to_vec())
             .map(String::from_utf8)
@@ -42,10 +89,51 @@ impl KVEngine for SledKVEngine {
     }
 
     fn remove(&self, key: String) -> Result<()> {
-        self.0
-            .remove(key.to_owned())?
-            .ok_or(KVError::FailGet(key))?;
-        self.0.flush()?;
+        let db = self.writable()?;
+        db.remove(key.to_owned())?.ok_or(KVError::FailGet(key))?;
+        db.flush()?;
+        Ok(())
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<KVPair>> {
+        self.db
+            .scan_prefix(prefix)
+            .map(|item| {
+                let (key, val) = item?;
+                Ok(KVPair::new(
+                    String::from_utf8(key.to_vec())?,
+                    String::from_utf8(val.to_vec())?,
+                ))
+            })
+            .collect()
+    }
+
+    fn scan_from(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<Vec<KVPair>> {
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after.as_bytes().to_vec()),
+            _ => Bound::Included(prefix.as_bytes().to_vec()),
+        };
+        self.db
+            .range((start, Bound::Unbounded))
+            .take_while(|item| match item {
+                Ok((key, _)) => key.starts_with(prefix.as_bytes()),
+                Err(_) => true,
+            })
+            .take(limit)
+            .map(|item| {
+                let (key, val) = item?;
+                Ok(KVPair::new(
+                    String::from_utf8(key.to_vec())?,
+                    String::from_utf8(val.to_vec())?,
+                ))
+            })
+            .collect()
+    }
+
+    fn flush(&self) -> Result<()> {
+        if !self.read_only {
+            self.db.flush()?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,80 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Deserializer;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+
+/// Append-only write-ahead log of JSON records, truncated once the records
+/// it protects are persisted elsewhere.
+pub struct Wal<T> {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    len: u64,
+    sync: bool,
+    record: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> Wal<T> {
+    /// Opens the log at `path`, syncing every append to disk when `sync`.
+    pub fn open(path: &Path, sync: bool) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Wal {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+            len,
+            sync,
+            record: PhantomData,
+        })
+    }
+
+    /// Records logged so far, stopping at the first torn record.
+    pub fn replay(path: &Path) -> Result<Vec<T>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let reader = BufReader::new(File::open(path)?);
+        let records = Deserializer::from_reader(reader)
+            .into_iter::<T>()
+            .take_while(|record| record.is_ok())
+            .filter_map(|record| record.ok())
+            .collect();
+        Ok(records)
+    }
+
+    pub fn append(&mut self, record: &T) -> Result<()> {
+        let mut buf = serde_json::to_vec(record)?;
+        buf.push(b'\n');
+        self.writer.write_all(&buf)?;
+        self.writer.flush()?;
+        if self.sync {
+            self.writer.get_ref().sync_data()?;
+        }
+        self.len += buf.len() as u64;
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    /// Size in bytes of the log.
+    pub fn size(&self) -> u64 {
+        self.len
+    }
+
+    pub fn truncate(&mut self) -> Result<()> {
+        let file = File::create(&self.path)?;
+        file.sync_all()?;
+        self.writer = BufWriter::new(OpenOptions::new().append(true).open(&self.path)?);
+        self.len = 0;
+        Ok(())
+    }
+}
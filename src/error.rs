@@ -1,3 +1,4 @@
+use crate::common_struct::ErrorCode;
 use failure::Fail;
 use std::fs::File;
 use std::io;
@@ -21,8 +22,13 @@ pub enum KVError {
     StringError(String),
     #[fail(display = "An error occured with sled engine: {}", error)]
     Sled { error: sled::Error },
-    #[fail(display = "Wrong engine.")]
-    WrongEngine,
+    #[fail(
+        display = "Wrong engine: directory belongs to {} engine, not {}",
+        found, expected
+    )]
+    WrongEngine { expected: String, found: String },
+    #[fail(display = "Unsupported {} data format version {}", engine, version)]
+    UnsupportedFormat { engine: String, version: u32 },
     #[fail(display = "Fail to get value from {}", _0)]
     FailGet(String),
     #[fail(display = "Error reading entry from log file")]
@@ -35,6 +41,38 @@ pub enum KVError {
     NoResponse,
     #[fail(display = "{}", _0)]
     EngineNotFound(String),
+    #[fail(display = "Data directory {} is already in use", _0)]
+    DirectoryLocked(String),
+    #[fail(display = "The store is opened read-only")]
+    ReadOnly,
+    #[fail(display = "{}", _0)]
+    NotFound(String),
+    #[fail(display = "{}", _0)]
+    WrongType(String),
+    #[fail(display = "{}", _0)]
+    PreconditionFailed(String),
+    #[fail(display = "{}", _0)]
+    InvalidArgument(String),
+    #[fail(display = "Protocol error: {}", _0)]
+    Protocol(String),
+    #[fail(display = "{}", _0)]
+    UnsupportedProtocol(String),
+    #[fail(display = "Invalid configuration: {}", _0)]
+    InvalidConfig(String),
+    #[fail(display = "Invalid address: {}", _0)]
+    InvalidAddress(String),
+    #[fail(display = "TLS error: {}", _0)]
+    Tls(String),
+    #[fail(display = "Invalid engine layer: {}", _0)]
+    InvalidLayer(String),
+    #[fail(display = "Invalid key: {}", _0)]
+    InvalidKey(String),
+    #[fail(display = "Invalid value: {}", _0)]
+    InvalidValue(String),
+    #[fail(display = "Too large: {}", _0)]
+    TooLarge(String),
+    #[fail(display = "{} is not supported by this engine", _0)]
+    Unsupported(String),
     #[fail(display = "An error occurred.")]
     None,
 }
@@ -75,4 +113,37 @@ impl From<sled::Error> for KVError {
     }
 }
 
+impl KVError {
+    /// Code sent to clients when a request fails with this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            KVError::FailGet(_) | KVError::FailRemove | KVError::NotFound(_) => ErrorCode::NotFound,
+            KVError::WrongType(_) => ErrorCode::WrongType,
+            KVError::PreconditionFailed(_) => ErrorCode::PreconditionFailed,
+            KVError::ReadOnly => ErrorCode::ReadOnly,
+            KVError::InvalidKey(_)
+            | KVError::InvalidValue(_)
+            | KVError::InvalidArgument(_)
+            | KVError::TooLarge(_) => ErrorCode::InvalidArgument,
+            KVError::Protocol(_) => ErrorCode::Protocol,
+            KVError::UnsupportedProtocol(_) => ErrorCode::UnsupportedVersion,
+            _ => ErrorCode::Internal,
+        }
+    }
+
+    /// Error returned by clients for a failed request.
+    pub fn from_response(code: ErrorCode, message: String) -> KVError {
+        match code {
+            ErrorCode::NotFound => KVError::NotFound(message),
+            ErrorCode::WrongType => KVError::WrongType(message),
+            ErrorCode::PreconditionFailed => KVError::PreconditionFailed(message),
+            ErrorCode::ReadOnly => KVError::ReadOnly,
+            ErrorCode::InvalidArgument => KVError::InvalidArgument(message),
+            ErrorCode::Protocol => KVError::Protocol(message),
+            ErrorCode::UnsupportedVersion => KVError::UnsupportedProtocol(message),
+            ErrorCode::Internal | ErrorCode::Unknown => KVError::StringError(message),
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, KVError>;
@@ -0,0 +1,7 @@
+//! Messages and client of the gRPC `KvService`, generated from
+//! `proto/kvs.proto`.
+//!
+//! `kv_service_client::KvServiceClient` is an alternative to `KVClient`,
+//! talking to the service returned by `KVServer::grpc_service`.
+
+tonic::include_proto!("kvs");
@@ -1,13 +1,37 @@
+pub use addr::{ServerAddr, DEFAULT_PORT};
+#[cfg(feature = "async")]
+pub use client::AsyncKVClient;
 pub use client::KVClient;
-pub use common_struct::{KVPair, KVRequest, KVResponse};
-pub use engines::{KVEngine, KVStore, SledKVEngine};
+pub use common_struct::{ErrorCode, KVPair, KVRequest, KVResponse};
+pub use engines::{
+    build_stack, open_engine, BTreeKVEngine, BoxedKVEngine, CacheStats, EngineMeta, EngineOptions,
+    KVCache, KVEngine, KVMetrics, KVPrefix, KVStore, KVValidate, LayerConfig, LsmKVEngine,
+    MemoryKVEngine, SledKVEngine,
+};
+pub use config::{CompactionConfig, Durability, ServerConfig, ServerProtocol};
 pub use error::{KVError, Result};
-pub use server::KVServer;
+pub use lock::DirLock;
+pub use protocol::{Capability, Codec, MAX_FRAME_LEN, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+#[cfg(feature = "async")]
+pub use server::AsyncKVServer;
+#[cfg(feature = "grpc")]
+pub use server::GrpcService;
+pub use server::{Change, HttpGateway, KVServer, ServerHandle, ShutdownHandle};
+pub use tls::{ClientTls, ServerTls};
 
+#[macro_use]
 extern crate slog;
 
+mod addr;
 mod client;
 mod common_struct;
+mod config;
 mod engines;
 mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod lock;
+mod protocol;
 mod server;
+mod tls;
+mod transport;
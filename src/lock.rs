@@ -0,0 +1,57 @@
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use crate::error::{KVError, Result};
+
+pub const LOCK_FILE: &str = "kvs.lock";
+
+/// OS advisory lock on the lock file of a data directory, released when
+/// dropped or when the process dies.
+///
+/// Writers hold it exclusively, read-only opens share it, so a directory is
+/// either written by one process or read by any number of them.
+pub struct DirLock {
+    // `None` for a shared lock on a directory without lock file
+    file: Option<File>,
+}
+
+impl DirLock {
+    /// Creates `dir_path` if missing and locks it exclusively.
+    pub fn exclusive(dir_path: &Path) -> Result<DirLock> {
+        fs::create_dir_all(dir_path)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(dir_path.join(LOCK_FILE))?;
+        file.try_lock_exclusive()
+            .map_err(|_| KVError::DirectoryLocked(dir_path.display().to_string()))?;
+        Ok(DirLock { file: Some(file) })
+    }
+
+    /// Locks the existing directory `dir_path` in shared mode, without
+    /// writing to it: a directory without lock file, written before locks
+    /// existed or never written, is not locked.
+    pub fn shared(dir_path: &Path) -> Result<DirLock> {
+        let file = match File::open(dir_path.join(LOCK_FILE)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && dir_path.is_dir() => {
+                return Ok(DirLock { file: None });
+            }
+            Err(e) => return Err(e.into()),
+        };
+        file.try_lock_shared()
+            .map_err(|_| KVError::DirectoryLocked(dir_path.display().to_string()))?;
+        Ok(DirLock { file: Some(file) })
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            let _ = file.unlock();
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Deserializer;
+use std::convert::TryInto;
+use std::io::{Read, Write};
+
+use crate::error::{KVError, Result};
+
+/// Version of the protocol spoken by this crate.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest version still spoken. Peers that skip the handshake are served
+/// as well: they predate versioning.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Largest binary message accepted from a peer, in bytes, so that a bogus
+/// length prefix cannot make the reader allocate without limit.
+pub const MAX_FRAME_LEN: u32 = 64 << 20;
+
+/// Optional protocol feature, offered by the client in its `Hello` and
+/// kept in the answer when the server supports it too.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Batching,
+    Scans,
+    Ttl,
+    Compression,
+    /// Capability of a newer peer, never granted.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Capabilities implemented by this crate.
+pub const CAPABILITIES: &[Capability] = &[Capability::Scans];
+
+/// Encoding of the messages following the handshake, which is always JSON.
+///
+/// JSON messages are written back to back, as before codecs existed, while
+/// binary ones are prefixed with their length as a big endian `u32`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+    MessagePack,
+    Bincode,
+    /// Codec of a newer peer, never selected.
+    #[serde(other)]
+    Unknown,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::Json
+    }
+}
+
+/// Codecs implemented by this crate, from the most preferred.
+pub const CODECS: &[Codec] = &[Codec::Json, Codec::MessagePack, Codec::Bincode];
+
+impl Codec {
+    /// `message` encoded and framed, ready to be written to a peer.
+    pub fn encode<T: Serialize>(self, message: &T) -> Result<Vec<u8>> {
+        let payload = match self {
+            Codec::Json => return Ok(serde_json::to_vec(message)?),
+            Codec::MessagePack => rmp_serde::to_vec(message).map_err(protocol_error)?,
+            Codec::Bincode => bincode::serialize(message).map_err(protocol_error)?,
+            Codec::Unknown => return Err(unknown_codec()),
+        };
+        let len: u32 = payload
+            .len()
+            .try_into()
+            .ok()
+            .filter(|len| *len <= MAX_FRAME_LEN)
+            .ok_or_else(|| frame_too_large(payload.len()))?;
+
+        let mut frame = Vec::with_capacity(4 + payload.len());
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.extend_from_slice(&payload);
+        Ok(frame)
+    }
+
+    /// Decodes an unframed message.
+    pub fn decode<T: DeserializeOwned>(self, payload: &[u8]) -> Result<T> {
+        match self {
+            Codec::Json => serde_json::from_slice(payload).map_err(protocol_error),
+            Codec::MessagePack => rmp_serde::from_slice(payload).map_err(protocol_error),
+            Codec::Bincode => bincode::deserialize(payload).map_err(protocol_error),
+            Codec::Unknown => Err(unknown_codec()),
+        }
+    }
+
+    pub fn write<T: Serialize, W: Write>(self, writer: &mut W, message: &T) -> Result<()> {
+        writer.write_all(&self.encode(message)?)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads one message, leaving whatever follows it in `reader`.
+    pub fn read<T: DeserializeOwned, R: Read>(self, mut reader: R) -> Result<T> {
+        if self == Codec::Json {
+            let mut deserializer = Deserializer::from_reader(reader);
+            return T::deserialize(&mut deserializer).map_err(protocol_error);
+        }
+
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let mut payload = vec![0; frame_len(len)?];
+        reader.read_exact(&mut payload)?;
+        self.decode(&payload)
+    }
+}
+
+/// Server side of the handshake: the version to speak with a client
+/// offering `version`, and the capabilities both sides support.
+pub fn negotiate(version: u32, offered: &[Capability]) -> Result<(u32, Vec<Capability>)> {
+    check_version(version)?;
+    let shared = CAPABILITIES
+        .iter()
+        .filter(|capability| offered.contains(capability))
+        .cloned()
+        .collect();
+    Ok((version.min(PROTOCOL_VERSION), shared))
+}
+
+pub fn check_version(version: u32) -> Result<()> {
+    if version < MIN_PROTOCOL_VERSION {
+        return Err(unsupported_version(version));
+    }
+    Ok(())
+}
+
+/// Client side of the handshake: the server may only negotiate down to a
+/// version the client speaks.
+pub fn check_negotiated(version: u32) -> Result<()> {
+    if version < MIN_PROTOCOL_VERSION || version > PROTOCOL_VERSION {
+        return Err(unsupported_version(version));
+    }
+    Ok(())
+}
+
+fn unsupported_version(version: u32) -> KVError {
+    KVError::UnsupportedProtocol(format!(
+        "unsupported protocol version {}, versions {} to {} are supported",
+        version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+    ))
+}
+
+/// Codec both sides support, picked in the client's order of preference.
+pub fn negotiate_codec(offered: &[Codec]) -> Codec {
+    offered
+        .iter()
+        .find(|codec| CODECS.contains(codec))
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn protocol_error<E: std::fmt::Display>(e: E) -> KVError {
+    KVError::Protocol(e.to_string())
+}
+
+/// Length of the payload announced by a frame prefix.
+fn frame_len(prefix: [u8; 4]) -> Result<usize> {
+    let len = u32::from_be_bytes(prefix);
+    if len > MAX_FRAME_LEN {
+        return Err(frame_too_large(len as usize));
+    }
+    Ok(len as usize)
+}
+
+fn frame_too_large(len: usize) -> KVError {
+    KVError::Protocol(format!(
+        "message of {} bytes exceeds the {} bytes limit",
+        len, MAX_FRAME_LEN
+    ))
+}
+
+fn unknown_codec() -> KVError {
+    KVError::Protocol("unknown codec".to_owned())
+}
+
+/// Reads one message from `reader`, JSON ones chunk by chunk as peers keep
+/// the connection open while waiting for the answer.
+#[cfg(feature = "async")]
+pub async fn read_message<T, R>(reader: &mut R, codec: Codec) -> Result<T>
+where
+    T: DeserializeOwned,
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    if codec != Codec::Json {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len).await?;
+        let mut payload = vec![0; frame_len(len)?];
+        reader.read_exact(&mut payload).await?;
+        return codec.decode(&payload);
+    }
+
+    // JSON messages are only parsed once complete, and bounded like frames
+    let mut buf = Vec::new();
+    let mut chunk = [0; 512];
+    let mut scanner = JsonScanner::default();
+    loop {
+        let len = reader.read(&mut chunk).await?;
+        if len == 0 {
+            return Err(KVError::Protocol("connection closed mid-message".to_owned()));
+        }
+        buf.extend_from_slice(&chunk[..len]);
+
+        if let Some(end) = scanner.scan(&buf)? {
+            return serde_json::from_slice(&buf[..end])
+                .map_err(|e| KVError::Protocol(e.to_string()));
+        }
+        if buf.len() > MAX_FRAME_LEN as usize {
+            return Err(frame_too_large(buf.len()));
+        }
+    }
+}
+
+/// Finds the end of the JSON object, array or string at the start of a
+/// buffer growing between calls, each byte being looked at once.
+#[cfg(feature = "async")]
+#[derive(Default)]
+struct JsonScanner {
+    pos: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+#[cfg(feature = "async")]
+impl JsonScanner {
+    /// Length of the value once `buf` holds all of it.
+    fn scan(&mut self, buf: &[u8]) -> Result<Option<usize>> {
+        while self.pos < buf.len() {
+            let byte = buf[self.pos];
+            self.pos += 1;
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                    if self.depth == 0 {
+                        return Ok(Some(self.pos));
+                    }
+                }
+                continue;
+            }
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' if self.depth > 0 => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        return Ok(Some(self.pos));
+                    }
+                }
+                b' ' | b'\t' | b'\r' | b'\n' => {}
+                // messages are never bare numbers or literals
+                _ if self.depth == 0 => {
+                    return Err(KVError::Protocol("expected a JSON message".to_owned()));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
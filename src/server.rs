@@ -1,15 +1,139 @@
-use serde::Deserialize;
-use serde_json::Deserializer;
 use slog::Logger;
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, BufWriter};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+#[cfg(feature = "async")]
+use tokio::sync::broadcast;
 
+use self::resp::RespState;
 use crate::common_struct::{KVRequest, KVResponse};
+use crate::config::ServerProtocol;
 use crate::engines::KVEngine;
 use crate::error::{KVError, Result};
+use crate::protocol::{negotiate, negotiate_codec, Capability, Codec};
+use crate::tls::ServerTls;
+use crate::transport::{Listener, Stream, Waker};
 
+#[cfg(feature = "async")]
+pub use self::async_server::AsyncKVServer;
+#[cfg(feature = "grpc")]
+pub use self::grpc::GrpcService;
+pub use self::http::HttpGateway;
+
+#[cfg(feature = "async")]
+mod async_server;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod http;
+mod resp;
+
+// how often threads waiting on a stream or channel check for a shutdown
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+// longest a peer may stall a read or write, the TLS handshake included,
+// before its connection is dropped
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_CLIENTS: usize = 1024;
+// changes queued for an async watcher before it lags behind
+#[cfg(feature = "async")]
+const WATCH_BUFFER: usize = 1024;
+
+#[derive(Clone)]
 pub struct KVServer<E: KVEngine> {
     engine: E,
+    read_only: bool,
+    protocol: ServerProtocol,
+    threads: usize,
+    drain_timeout: Duration,
+    max_clients: usize,
+    socket_mode: Option<u32>,
+    tls: Option<ServerTls>,
+    shutdown: Arc<AtomicBool>,
+    wakers: Arc<Mutex<Vec<Waker>>>,
+    resp: Arc<RespState>,
+    watchers: Arc<Mutex<Vec<Sender<Change>>>>,
+    #[cfg(feature = "async")]
+    changes: broadcast::Sender<Change>,
+    // held shared by `set` and `remove`, exclusively by conditional writes
+    writes: Arc<RwLock<()>>,
+}
+
+/// Write made through a `KVServer`, whatever the protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Set { key: String, val: String },
+    Remove { key: String },
+}
+
+/// Stops a running `KVServer` from another thread, e.g. a signal handler.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    stopped: Arc<AtomicBool>,
+    wakers: Arc<Mutex<Vec<Waker>>>,
+}
+
+impl ShutdownHandle {
+    /// Makes `run` stop accepting connections, let the requests in flight
+    /// complete, flush the engine and return.
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // threads blocked accepting connections only notice the flag once
+        // a connection comes in
+        for waker in self.wakers.lock().unwrap().iter() {
+            waker.wake();
+        }
+    }
+}
+
+/// Server running on background threads, started by `KVServer::spawn`.
+///
+/// Dropping the handle stops the server and waits for it.
+pub struct ServerHandle {
+    addr: SocketAddr,
+    shutdown: ShutdownHandle,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl ServerHandle {
+    /// Address the server listens on, with the actual port when it was
+    /// asked to bind port 0.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Shuts the server down and waits for it to stop.
+    pub fn stop(self) -> Result<()> {
+        self.shutdown.shutdown();
+        self.join()
+    }
+
+    /// Waits for the server to stop, returning the error that stopped it.
+    pub fn join(mut self) -> Result<()> {
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|_| Err(KVError::StringError("server thread panicked".to_owned()))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.shutdown.shutdown();
+            let _ = thread.join();
+        }
+    }
 }
 
 impl<E: KVEngine> KVServer<E> {
@@ -17,67 +141,357 @@ impl<E: KVEngine> KVServer<E> {
         // let current_path = std::env::current_dir()?;
         // let kvs = KVStore::open(&current_path)?;
         // Ok(KVServer { kvs })
-        KVServer { engine }
+        KVServer {
+            engine,
+            read_only: false,
+            protocol: ServerProtocol::Kvs,
+            threads: 1,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            max_clients: DEFAULT_MAX_CLIENTS,
+            socket_mode: None,
+            tls: None,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            wakers: Arc::new(Mutex::new(Vec::new())),
+            resp: Arc::new(RespState::default()),
+            watchers: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "async")]
+            changes: broadcast::channel(WATCH_BUFFER).0,
+            writes: Arc::new(RwLock::new(())),
+        }
     }
 
-    pub fn run(&mut self, addr: String, log: &Logger) -> Result<()> {
-        let listener = TcpListener::bind(addr)?;
+    /// Server answering `Get`, `Scan` and `Stats` requests only, every
+    /// write being rejected before it reaches the engine.
+    pub fn read_only(engine: E) -> Self {
+        KVServer {
+            read_only: true,
+            ..KVServer::new(engine)
+        }
+    }
+
+    /// Speaks `protocol` instead of the kvs protocol. RESP clients keep their
+    /// connection open, so each one is served by a thread of its own, up to
+    /// `max_clients` of them.
+    pub fn protocol(mut self, protocol: ServerProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Serves connections from `threads` threads, each holding a clone of
+    /// the engine.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
 
-        for stream in listener.incoming() {
-            let stream = stream?;
-            self.handle_connection(&stream, &log)?;
+    /// How long a shutdown waits for the requests in flight, 5 seconds by
+    /// default. The engine is flushed once it elapses, even though requests
+    /// still running may write to it afterwards, unflushed.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// How many RESP clients are served at once, 1024 by default. Clients
+    /// connecting past the limit are answered an error.
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients.max(1);
+        self
+    }
+
+    /// Permissions given to the socket file when serving a `unix:PATH`
+    /// address, such as `0o660`. The umask applies otherwise.
+    pub fn socket_mode(mut self, mode: u32) -> Self {
+        self.socket_mode = Some(mode);
+        self
+    }
+
+    /// Encrypts connections with TLS, over TCP and Unix sockets alike.
+    /// Clients must then connect with `KVClient::with_tls`.
+    pub fn tls(mut self, tls: ServerTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            stopped: Arc::clone(&self.shutdown),
+            wakers: Arc::clone(&self.wakers),
         }
+    }
 
-        Ok(())
+    /// Changes made through this server and its clones from now on, until
+    /// the receiver is dropped.
+    pub fn watch(&self) -> Receiver<Change> {
+        let (sender, receiver) = mpsc::channel();
+        self.watchers.lock().unwrap().push(sender);
+        receiver
     }
 
-    fn handle_connection(&mut self, stream: &TcpStream, log: &Logger) -> Result<()> {
-        let _peer_addr = stream.peer_addr()?;
-        let mut reader = BufReader::new(stream);
+    /// `watch` for async tasks, which are woken by each change. A receiver
+    /// falling `WATCH_BUFFER` changes behind gets `RecvError::Lagged`.
+    #[cfg(feature = "async")]
+    pub fn watch_async(&self) -> broadcast::Receiver<Change> {
+        self.changes.subscribe()
+    }
 
-        let mut buffer = [0; 512];
-        reader.read(&mut buffer)?; // need check if read_exact works here 
+    /// Serves connections on `addr` until a shutdown is requested, then
+    /// returns once the engine is flushed. A stopped server does not run
+    /// again.
+    ///
+    /// `addr` is a comma-separated list of `ServerAddr`, such as
+    /// `127.0.0.1:4000,[::1]:4000`, `localhost` or `unix:PATH`. Host names
+    /// are bound on each of their addresses, and Unix socket files removed
+    /// when the server stops.
+    pub fn run(&mut self, addr: String, log: &Logger) -> Result<()>
+    where
+        E: Send + 'static,
+    {
+        let listeners = Listener::bind_all(&addr, self.socket_mode)?;
+        self.run_on(listeners, log)
+    }
 
-        let executed = self.execute_cmd(buffer, log);
-        let resp: KVResponse;
-        match executed {
-            Ok(val) => resp = KVResponse::Ok(Some(val)),
-            Err(error) => resp = KVResponse::Err(format!("error {}", error)),
+    /// Binds `addr`, which may use port 0, then serves connections from
+    /// background threads until the returned handle stops the server.
+    pub fn spawn(mut self, addr: String, log: &Logger) -> Result<ServerHandle>
+    where
+        E: Send + 'static,
+    {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shutdown = self.shutdown_handle();
+        let log = log.clone();
+        let thread = thread::spawn(move || self.run_on(vec![Listener::Tcp(listener)], &log));
+
+        Ok(ServerHandle {
+            addr,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    fn run_on(&mut self, listeners: Vec<Listener>, log: &Logger) -> Result<()>
+    where
+        E: Send + 'static,
+    {
+        // registered before the acceptors check the flag, so that a shutdown
+        // either is seen by them or wakes them up
+        *self.wakers.lock().unwrap() = listeners
+            .iter()
+            .map(Listener::waker)
+            .collect::<io::Result<_>>()?;
+
+        // one thread blocks accepting connections on each listener, and
+        // hands them to the workers
+        let (sender, receiver) = mpsc::channel();
+        let mut acceptors = Vec::new();
+        for listener in listeners {
+            let shutdown = self.shutdown_handle();
+            let sender = sender.clone();
+            acceptors.push(thread::spawn(move || {
+                let accepted = accept_connections(&listener, &shutdown.stopped, &sender);
+                // on error, the other acceptors stop as well
+                if accepted.is_err() {
+                    shutdown.shutdown();
+                }
+                accepted
+            }));
         }
+        drop(sender);
+        let connections = Arc::new(Mutex::new(receiver));
 
-        let mut buf = vec![];
-        serde_json::to_writer(&mut buf, &resp)?;
+        let (done_sender, done_receiver) = mpsc::channel();
+        for _ in 1..self.threads {
+            let connections = Arc::clone(&connections);
+            let mut worker = self.clone();
+            let log = log.clone();
+            let done_sender = done_sender.clone();
+            thread::spawn(move || {
+                worker.serve(&connections, &log);
+                // the engine clone is released before `run` returns
+                drop(worker);
+                let _ = done_sender.send(());
+            });
+        }
 
-        let mut writer = BufWriter::new(stream);
-        writer.write_all(&buf)?;
+        // returns once every acceptor stopped and the connections they
+        // accepted are taken
+        self.serve(&connections, log);
+        let mut served = Ok(());
+        for acceptor in acceptors {
+            match acceptor.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => served = Err(e.into()),
+                Err(_) => served = Err(KVError::StringError("acceptor panicked".to_owned())),
+            }
+        }
 
-        Ok(())
+        let deadline = Instant::now() + self.drain_timeout;
+        let mut drained = true;
+        for _ in 1..self.threads {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if done_receiver.recv_timeout(left).is_err() {
+                drained = false;
+                break;
+            }
+        }
+        while drained && self.resp.connections() > 0 {
+            if Instant::now() >= deadline {
+                drained = false;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        if !drained {
+            // requests still running may write after the flush below
+            warn!(log, "Shutdown timeout reached with requests in flight");
+        }
+
+        self.engine.flush()?;
+        info!(log, "Server stopped");
+        served
     }
 
-    fn execute_cmd(&mut self, buffer: [u8; 512], _log: &Logger) -> Result<String> {
-        let mut deserializer = Deserializer::from_slice(&buffer);
+    /// Serves the accepted connections until every acceptor stopped.
+    fn serve(&mut self, connections: &Mutex<Receiver<(Stream, String)>>, log: &Logger)
+    where
+        E: Send + 'static,
+    {
+        loop {
+            // the lock is released before serving the connection
+            let received = connections.lock().unwrap().recv();
+            let (stream, peer) = match received {
+                Ok(connection) => connection,
+                Err(_) => return,
+            };
+            let timeouts = stream
+                .set_read_timeout(Some(IO_TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(IO_TIMEOUT)));
+            if let Err(e) = timeouts {
+                warn!(log, "Connection from {} failed: {}", peer, e);
+                continue;
+            }
+            let stream = match &self.tls {
+                Some(tls) => stream.accept_tls(tls),
+                None => stream,
+            };
+            match self.protocol {
+                ServerProtocol::Kvs => {
+                    if let Err(e) = self.handle_connection(&stream) {
+                        warn!(log, "Connection from {} failed: {}", peer, e);
+                    }
+                }
+                ServerProtocol::Resp => self.spawn_resp_connection(stream, peer, log),
+            }
+        }
+    }
 
-        let req = KVRequest::deserialize(&mut deserializer)?;
+    /// Answers one request, preceded by a handshake unless the client
+    /// predates protocol versioning.
+    fn handle_connection(&mut self, stream: &Stream) -> Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut writer = BufWriter::new(stream);
+
+        // the handshake, and requests skipping it, are always JSON
+        let mut codec = Codec::Json;
+        let mut req = codec.read(&mut reader);
+        if let Ok(KVRequest::Hello {
+            version,
+            capabilities,
+            codecs,
+        }) = req
+        {
+            let resp = hello(version, &capabilities, &codecs);
+            codec.write(&mut writer, &resp)?;
+            match resp {
+                KVResponse::Hello { codec: agreed, .. } => codec = agreed,
+                _ => return Ok(()),
+            }
+            req = codec.read(&mut reader);
+        }
 
+        let resp = response(req.and_then(|req| self.execute_request(req)));
+        codec.write(&mut writer, &resp)
+    }
+
+    /// Answer to `req`, `None` standing for a missing key.
+    fn execute_request(&mut self, req: KVRequest) -> Result<Option<String>> {
         match req {
+            KVRequest::Set { .. } | KVRequest::Rm { .. } if self.read_only => {
+                Err(KVError::ReadOnly)
+            }
             KVRequest::Get { key } => self.execute_get_cmd(key),
-            KVRequest::Set { key, val } => self.execute_set_cmd(key, val),
-            KVRequest::Rm { key } => self.execute_rm_cmd(key),
+            KVRequest::Set { key, val } => self.execute_set_cmd(key, val).map(Some),
+            KVRequest::Rm { key } => self.execute_rm_cmd(key).map(Some),
+            KVRequest::Scan { prefix } => self.execute_scan_cmd(prefix).map(Some),
+            KVRequest::Stats => self.execute_stats_cmd().map(Some),
+            KVRequest::Hello { .. } => Err(KVError::Protocol("unexpected handshake".to_owned())),
         }
     }
 
-    fn execute_get_cmd(&mut self, key: String) -> Result<String> {
-        if let Some(value) = self.engine.get(key)? {
-            println!("{}", value);
-            Ok(value)
-        } else {
-            println!("Key not found");
-            Ok(String::from("Key not found"))
+    /// Stores `val` under `key`, notifying watchers.
+    fn set(&self, key: String, val: String) -> Result<()> {
+        let _writes = self.writes.read().unwrap();
+        self.set_locked(key, val)
+    }
+
+    /// Removes `key`, notifying watchers.
+    fn remove(&self, key: String) -> Result<()> {
+        let _writes = self.writes.read().unwrap();
+        self.remove_locked(key)
+    }
+
+    /// Stops every write made through this server and its clones until the
+    /// guard is dropped, so that a check and the writes depending on it are
+    /// atomic. The guard holder writes with `set_locked` and `remove_locked`.
+    fn lock_writes(&self) -> RwLockWriteGuard<'_, ()> {
+        self.writes.write().unwrap()
+    }
+
+    /// `set` for callers already holding the write lock, shared or not.
+    fn set_locked(&self, key: String, val: String) -> Result<()> {
+        self.engine.set(key.to_owned(), val.to_owned())?;
+        self.notify(Change::Set { key, val });
+        Ok(())
+    }
+
+    /// `remove` for callers already holding the write lock, shared or not.
+    fn remove_locked(&self, key: String) -> Result<()> {
+        self.engine.remove(key.to_owned())?;
+        self.notify(Change::Remove { key });
+        Ok(())
+    }
+
+    fn notify(&self, change: Change) {
+        // only fails while nobody is watching
+        #[cfg(feature = "async")]
+        let _ = self.changes.send(change.clone());
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.retain(|watcher| watcher.send(change.clone()).is_ok());
+    }
+
+    fn writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(KVError::ReadOnly);
         }
+        Ok(())
+    }
+
+    fn execute_scan_cmd(&mut self, prefix: String) -> Result<String> {
+        let pairs = self.engine.scan(&prefix)?;
+        Ok(serde_json::to_string(&pairs)?)
+    }
+
+    fn execute_stats_cmd(&mut self) -> Result<String> {
+        let stats: BTreeMap<String, u64> = self.engine.stats().into_iter().collect();
+        Ok(serde_json::to_string(&stats)?)
+    }
+
+    fn execute_get_cmd(&mut self, key: String) -> Result<Option<String>> {
+        self.engine.get(key)
     }
 
     fn execute_set_cmd(&mut self, key: String, val: String) -> Result<String> {
-        self.engine.set(key.to_owned(), val.to_owned())?;
+        self.set(key.to_owned(), val.to_owned())?;
         Ok(format!(
             "set key: {} value: {} succesffully done !",
             key, val
@@ -85,12 +499,47 @@ impl<E: KVEngine> KVServer<E> {
     }
 
     fn execute_rm_cmd(&mut self, key: String) -> Result<String> {
-        match self.engine.remove(key.to_owned()) {
-            Ok(()) => Ok(format!("rm key: {} succesffully done !", key)),
-            Err(_) => {
-                println!("Key not found");
-                Err(KVError::FailGet(key))
-            }
+        self.remove(key.to_owned())?;
+        Ok(format!("rm key: {} succesffully done !", key))
+    }
+}
+
+/// Sends the connections accepted by `listener` to the workers until a
+/// shutdown is requested.
+fn accept_connections(
+    listener: &Listener,
+    stopped: &AtomicBool,
+    connections: &Sender<(Stream, String)>,
+) -> io::Result<()> {
+    while !stopped.load(Ordering::SeqCst) {
+        let connection = listener.accept()?;
+        // the connection waking us up for the shutdown is not served
+        if stopped.load(Ordering::SeqCst) || connections.send(connection).is_err() {
+            break;
         }
     }
+    Ok(())
+}
+
+/// Answer to the handshake of a client offering `version`, `capabilities`
+/// and `codecs`.
+fn hello(version: u32, capabilities: &[Capability], codecs: &[Codec]) -> KVResponse {
+    match negotiate(version, capabilities) {
+        Ok((version, capabilities)) => KVResponse::Hello {
+            version,
+            capabilities,
+            codec: negotiate_codec(codecs),
+        },
+        Err(error) => response(Err(error)),
+    }
+}
+
+fn response(executed: Result<Option<String>>) -> KVResponse {
+    match executed {
+        Ok(val) => KVResponse::Ok(val),
+        Err(error) => KVResponse::Err {
+            code: error.code(),
+            message: error.to_string(),
+        },
+    }
 }
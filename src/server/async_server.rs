@@ -0,0 +1,128 @@
+use slog::Logger;
+use std::future::{self, Future};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task;
+
+use super::{hello, response, KVServer};
+use crate::common_struct::{KVRequest, KVResponse};
+use crate::engines::KVEngine;
+use crate::error::{KVError, Result};
+use crate::protocol::{read_message, Codec};
+
+/// Tokio flavour of `KVServer`, speaking the same protocol.
+///
+/// Each connection is a task, so idle connections cost no thread, while
+/// engine calls run on tokio's blocking pool.
+pub struct AsyncKVServer<E: KVEngine> {
+    server: KVServer<E>,
+}
+
+impl<E: KVEngine + Send + 'static> AsyncKVServer<E> {
+    pub fn new(engine: E) -> Self {
+        AsyncKVServer {
+            server: KVServer::new(engine),
+        }
+    }
+
+    /// Server answering `Get`, `Scan` and `Stats` requests only.
+    pub fn read_only(engine: E) -> Self {
+        AsyncKVServer {
+            server: KVServer::read_only(engine),
+        }
+    }
+
+    /// How long a shutdown waits for the connections already accepted, 5
+    /// seconds by default. The engine is flushed once it elapses.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.server = self.server.drain_timeout(timeout);
+        self
+    }
+
+    /// Serves connections on `addr` until the task is dropped.
+    pub async fn run(self, addr: String, log: &Logger) -> Result<()> {
+        let listener = TcpListener::bind(&addr).await?;
+        self.serve(listener, future::pending(), log).await
+    }
+
+    /// Serves connections accepted by `listener` until `shutdown` completes,
+    /// then waits up to the drain timeout for the connections already
+    /// accepted, idle ones included, and flushes the engine.
+    pub async fn serve<F>(self, mut listener: TcpListener, shutdown: F, log: &Logger) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        // each connection holds a sender, so the channel closes once all ended
+        let (done, mut all_done) = mpsc::channel::<()>(1);
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, peer) = accepted?;
+                    let server = self.server.clone();
+                    let log = log.clone();
+                    let done = done.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(server, stream).await {
+                            warn!(log, "Connection from {} failed: {}", peer, e);
+                        }
+                        drop(done);
+                    });
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+
+        drop(done);
+        let drained = tokio::time::timeout(self.server.drain_timeout, all_done.recv()).await;
+        if drained.is_err() {
+            warn!(log, "Shutdown timeout reached with requests in flight");
+        }
+
+        let server = self.server;
+        task::spawn_blocking(move || server.engine.flush())
+            .await
+            .map_err(|e| KVError::StringError(e.to_string()))??;
+        info!(log, "Server stopped");
+        Ok(())
+    }
+}
+
+async fn handle_connection<E>(mut server: KVServer<E>, mut stream: TcpStream) -> Result<()>
+where
+    E: KVEngine + Send + 'static,
+{
+    // the handshake, and requests skipping it, are always JSON
+    let mut codec = Codec::Json;
+    let mut req = read_message(&mut stream, codec).await;
+    if let Ok(KVRequest::Hello {
+        version,
+        capabilities,
+        codecs,
+    }) = req
+    {
+        let resp = hello(version, &capabilities, &codecs);
+        stream.write_all(&codec.encode(&resp)?).await?;
+        match resp {
+            KVResponse::Hello { codec: agreed, .. } => codec = agreed,
+            _ => return Ok(()),
+        }
+        req = read_message(&mut stream, codec).await;
+    }
+
+    let resp = match req {
+        Ok(req) => {
+            // engines block on I/O and locks
+            let executed = task::spawn_blocking(move || server.execute_request(req))
+                .await
+                .map_err(|e| KVError::StringError(e.to_string()))?;
+            response(executed)
+        }
+        Err(e) => response(Err(e)),
+    };
+
+    stream.write_all(&codec.encode(&resp)?).await?;
+    Ok(())
+}
@@ -0,0 +1,290 @@
+use slog::Logger;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::mpsc::{self, Sender};
+use tokio::task;
+use tonic::{Request, Response, Status};
+
+use super::{Change, KVServer, POLL_INTERVAL, WATCH_BUFFER};
+use crate::common_struct::ErrorCode;
+use crate::engines::KVEngine;
+use crate::error::{KVError, Result};
+use crate::grpc::kv_service_server::{KvService, KvServiceServer};
+use crate::grpc::{
+    operation, operation_result, watch_event, BatchRequest, BatchResponse, DeleteRequest,
+    DeleteResponse, GetRequest, GetResponse, KeyValue, Operation, OperationError,
+    OperationResult, ScanRequest, SetRequest, SetResponse, WatchEvent, WatchRequest,
+};
+
+/// Pairs read from the engine at a time by a scan.
+const SCAN_BATCH: usize = 1000;
+/// Messages queued for a client before a scan waits for it to read them.
+const STREAM_BUFFER: usize = 128;
+
+type Stream<T> = mpsc::Receiver<std::result::Result<T, Status>>;
+
+/// gRPC `KvService` answering from the engine of a `KVServer`.
+///
+/// Engine calls run on tokio's blocking pool. Watchers are tasks woken by
+/// each change, and are disconnected once they fall `WATCH_BUFFER` changes
+/// behind.
+pub struct GrpcService<E: KVEngine> {
+    // tonic shares services between tasks, while engines are only `Send`
+    server: Mutex<KVServer<E>>,
+}
+
+impl<E: KVEngine + Send + 'static> KVServer<E> {
+    /// `KvService` sharing the engine, watchers and shutdown of this server,
+    /// to be added to a `tonic::transport::Server`.
+    pub fn grpc_service(&self) -> KvServiceServer<GrpcService<E>> {
+        KvServiceServer::new(GrpcService {
+            server: Mutex::new(self.clone()),
+        })
+    }
+
+    /// Serves gRPC connections accepted by `listener` until the server shuts
+    /// down.
+    pub async fn run_grpc(self, mut listener: TcpListener, log: &Logger) -> Result<()> {
+        info!(log, "gRPC service: {}", listener.local_addr()?);
+        let shutdown = Arc::clone(&self.shutdown);
+        let stopped = async move {
+            while !shutdown.load(Ordering::SeqCst) {
+                tokio::time::delay_for(POLL_INTERVAL).await;
+            }
+        };
+        tonic::transport::Server::builder()
+            .add_service(self.grpc_service())
+            .serve_with_incoming_shutdown(listener.incoming(), stopped)
+            .await
+            .map_err(|e| KVError::StringError(e.to_string()))
+    }
+}
+
+impl<E: KVEngine + Send + 'static> GrpcService<E> {
+    fn server(&self) -> KVServer<E> {
+        self.server.lock().unwrap().clone()
+    }
+
+    /// Runs `f` on a clone of the server from the blocking pool.
+    async fn blocking<T, F>(&self, f: F) -> std::result::Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(KVServer<E>) -> Result<T> + Send + 'static,
+    {
+        let server = self.server();
+        task::spawn_blocking(move || f(server))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(status)
+    }
+}
+
+#[tonic::async_trait]
+impl<E: KVEngine + Send + 'static> KvService for GrpcService<E> {
+    async fn get(
+        &self,
+        request: Request<GetRequest>,
+    ) -> std::result::Result<Response<GetResponse>, Status> {
+        let GetRequest { key } = request.into_inner();
+        let val = self.blocking(move |server| server.engine.get(key)).await?;
+        Ok(Response::new(get_response(val)))
+    }
+
+    async fn set(
+        &self,
+        request: Request<SetRequest>,
+    ) -> std::result::Result<Response<SetResponse>, Status> {
+        let SetRequest { key, value } = request.into_inner();
+        self.blocking(move |server| {
+            server.writable()?;
+            server.set(key, value)
+        })
+        .await?;
+        Ok(Response::new(SetResponse {}))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> std::result::Result<Response<DeleteResponse>, Status> {
+        let DeleteRequest { key } = request.into_inner();
+        self.blocking(move |server| {
+            server.writable()?;
+            server.remove(key)
+        })
+        .await?;
+        Ok(Response::new(DeleteResponse {}))
+    }
+
+    type ScanStream = Stream<KeyValue>;
+
+    async fn scan(
+        &self,
+        request: Request<ScanRequest>,
+    ) -> std::result::Result<Response<Self::ScanStream>, Status> {
+        let ScanRequest { prefix } = request.into_inner();
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(send_pairs(self.server(), prefix, sender));
+        Ok(Response::new(receiver))
+    }
+
+    type WatchStream = Stream<WatchEvent>;
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> std::result::Result<Response<Self::WatchStream>, Status> {
+        let WatchRequest { prefix } = request.into_inner();
+        let server = self.server();
+        // subscribe before answering, so no write made afterwards is missed
+        let changes = server.watch_async();
+        let (sender, receiver) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(forward_changes(server, changes, prefix, sender));
+        Ok(Response::new(receiver))
+    }
+
+    async fn batch(
+        &self,
+        request: Request<BatchRequest>,
+    ) -> std::result::Result<Response<BatchResponse>, Status> {
+        let BatchRequest { operations } = request.into_inner();
+        let results = self
+            .blocking(move |server| {
+                Ok(operations
+                    .into_iter()
+                    .map(|operation| execute_operation(&server, operation))
+                    .collect())
+            })
+            .await?;
+        Ok(Response::new(BatchResponse { results }))
+    }
+}
+
+/// Sends the pairs under `prefix` a batch at a time, waiting for the client
+/// to read them before reading the next batch.
+async fn send_pairs<E: KVEngine + Send + 'static>(
+    server: KVServer<E>,
+    prefix: String,
+    mut sender: Sender<std::result::Result<KeyValue, Status>>,
+) {
+    let mut after: Option<String> = None;
+    loop {
+        let (engine, from) = (server.engine.clone(), after.clone());
+        let prefix = prefix.clone();
+        let batch =
+            task::spawn_blocking(move || engine.scan_from(&prefix, from.as_deref(), SCAN_BATCH))
+                .await;
+        let pairs = match batch {
+            Ok(Ok(pairs)) => pairs,
+            Ok(Err(e)) => {
+                let _ = sender.send(Err(status(e))).await;
+                return;
+            }
+            Err(e) => {
+                let _ = sender.send(Err(Status::internal(e.to_string()))).await;
+                return;
+            }
+        };
+        let last_batch = pairs.len() < SCAN_BATCH;
+        for pair in pairs {
+            after = Some(pair.key.clone());
+            let pair = KeyValue {
+                key: pair.key,
+                value: pair.val,
+            };
+            if sender.send(Ok(pair)).await.is_err() {
+                return;
+            }
+        }
+        if last_batch {
+            return;
+        }
+    }
+}
+
+/// Sends the changes to keys under `prefix` until the watcher disconnects,
+/// falls `WATCH_BUFFER` changes behind, or the server shuts down.
+async fn forward_changes<E: KVEngine + Send + 'static>(
+    server: KVServer<E>,
+    mut changes: Receiver<Change>,
+    prefix: String,
+    mut sender: Sender<std::result::Result<WatchEvent, Status>>,
+) {
+    while !server.shutdown.load(Ordering::SeqCst) {
+        // waits for the next change, but not past a shutdown check
+        let change = match tokio::time::timeout(POLL_INTERVAL, changes.recv()).await {
+            Ok(Ok(change)) => change,
+            // lagging behind or closed
+            Ok(Err(_)) => break,
+            Err(_) => continue,
+        };
+        let event = match change {
+            Change::Set { key, val } => WatchEvent {
+                kind: watch_event::Kind::Set as i32,
+                key,
+                value: val,
+            },
+            Change::Remove { key } => WatchEvent {
+                kind: watch_event::Kind::Delete as i32,
+                key,
+                value: String::new(),
+            },
+        };
+        // a watcher too slow to keep up is dropped
+        if event.key.starts_with(&prefix) && sender.try_send(Ok(event)).is_err() {
+            break;
+        }
+    }
+}
+
+fn execute_operation<E: KVEngine>(server: &KVServer<E>, operation: Operation) -> OperationResult {
+    use self::operation_result::Outcome;
+
+    let outcome = match operation.op {
+        Some(operation::Op::Get(GetRequest { key })) => {
+            server.engine.get(key).map(|val| Outcome::Get(get_response(val)))
+        }
+        Some(operation::Op::Set(SetRequest { key, value })) => server
+            .writable()
+            .and_then(|_| server.set(key, value))
+            .map(|_| Outcome::Set(SetResponse {})),
+        Some(operation::Op::Delete(DeleteRequest { key })) => server
+            .writable()
+            .and_then(|_| server.remove(key))
+            .map(|_| Outcome::Delete(DeleteResponse {})),
+        None => Err(KVError::InvalidArgument("empty operation".to_owned())),
+    };
+    let outcome = outcome.unwrap_or_else(|e| {
+        Outcome::Error(OperationError {
+            code: format!("{:?}", e.code()),
+            message: e.to_string(),
+        })
+    });
+    OperationResult {
+        outcome: Some(outcome),
+    }
+}
+
+fn get_response(val: Option<String>) -> GetResponse {
+    GetResponse {
+        found: val.is_some(),
+        value: val.unwrap_or_default(),
+    }
+}
+
+fn status(error: KVError) -> Status {
+    let message = error.to_string();
+    match error.code() {
+        ErrorCode::NotFound => Status::not_found(message),
+        ErrorCode::WrongType | ErrorCode::PreconditionFailed => {
+            Status::failed_precondition(message)
+        }
+        ErrorCode::ReadOnly => Status::permission_denied(message),
+        ErrorCode::InvalidArgument | ErrorCode::Protocol | ErrorCode::UnsupportedVersion => {
+            Status::invalid_argument(message)
+        }
+        ErrorCode::Internal | ErrorCode::Unknown => Status::internal(message),
+    }
+}
@@ -0,0 +1,431 @@
+use serde::{Deserialize, Serialize};
+use slog::Logger;
+use std::io::{Cursor, Read};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response};
+
+use super::{KVServer, POLL_INTERVAL};
+use crate::addr::ServerAddr;
+use crate::common_struct::{ErrorCode, KVPair};
+use crate::engines::KVEngine;
+use crate::error::{KVError, Result};
+
+const MAX_BODY_LEN: u64 = 64 * 1024 * 1024;
+
+const TEXT: &str = "text/plain; charset=utf-8";
+const JSON: &str = "application/json";
+const BINARY: &str = "application/octet-stream";
+/// Start of the values stored from `application/octet-stream` bodies,
+/// followed by their base64 encoding. Text bodies may not start with it.
+const BINARY_TAG: &str = "\u{0}base64:";
+
+/// HTTP/JSON gateway to the engine of a `KVServer`, stopping along with it.
+///
+/// * `GET /keys/{key}` answers the value, as text by default, as JSON with
+///   `Accept: application/json` or as raw bytes with
+///   `Accept: application/octet-stream`, along with its `ETag`. Binary
+///   values are always answered as raw bytes, and 406 to JSON requests.
+/// * `PUT /keys/{key}` stores the body. `application/octet-stream` bodies
+///   are stored base64 encoded behind a `\0base64:` tag, as engines hold
+///   UTF-8 strings, and `application/json` ones must be a JSON string.
+///   `If-Match` and `If-None-Match: *` make the write conditional,
+///   answering 412 when the condition does not hold.
+/// * `DELETE /keys/{key}` removes the key, honouring `If-Match` as well.
+/// * `GET /keys?prefix=` answers the matching pairs as a JSON array.
+/// * `POST /batch` runs a JSON array of `{"op": "get"|"set"|"delete",
+///   "key": ..., "value": ...}` operations in order, answering the status
+///   and value of each. A batch is not atomic.
+///
+/// Bodies larger than 64 MiB are answered 413.
+#[derive(Clone)]
+pub struct HttpGateway<E: KVEngine> {
+    server: KVServer<E>,
+    http: Arc<tiny_http::Server>,
+}
+
+/// Operation of a `POST /batch` request.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BatchOp {
+    Get { key: String },
+    Set { key: String, value: String },
+    Delete { key: String },
+}
+
+/// Outcome of a batch operation.
+#[derive(Serialize)]
+struct BatchResult {
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+    etag: Option<String>,
+}
+
+impl Reply {
+    fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Reply {
+            status,
+            content_type,
+            body,
+            etag: None,
+        }
+    }
+
+    fn empty(status: u16) -> Self {
+        Reply::new(status, TEXT, Vec::new())
+    }
+
+    fn json<T: Serialize>(status: u16, body: &T) -> Result<Self> {
+        Ok(Reply::new(status, JSON, serde_json::to_vec(body)?))
+    }
+
+    fn error(status: u16, code: ErrorCode, message: &str) -> Self {
+        let body = serde_json::json!({ "code": code, "message": message });
+        Reply::new(status, JSON, body.to_string().into_bytes())
+    }
+
+    fn into_response(self) -> Response<Cursor<Vec<u8>>> {
+        let mut response = Response::from_data(self.body)
+            .with_status_code(self.status)
+            .with_header(header("Content-Type", self.content_type));
+        if let Some(etag) = self.etag {
+            response = response.with_header(header("ETag", &etag));
+        }
+        response
+    }
+}
+
+impl<E: KVEngine> KVServer<E> {
+    /// Binds an HTTP gateway on `addr` to the engine of this server, which
+    /// may use port 0. A host name is bound on the first of its addresses
+    /// that is available.
+    pub fn http_gateway(&self, addr: &str) -> Result<HttpGateway<E>> {
+        let addrs = addr.parse::<ServerAddr>()?.resolve()?;
+        let http = tiny_http::Server::http(&addrs[..])
+            .map_err(|e| KVError::StringError(format!("unable to bind {}: {}", addr, e)))?;
+        Ok(HttpGateway {
+            server: self.clone(),
+            http: Arc::new(http),
+        })
+    }
+}
+
+impl<E: KVEngine + Send + 'static> HttpGateway<E> {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.http.server_addr()
+    }
+
+    /// Serves requests from as many threads as the server until the server
+    /// shuts down.
+    pub fn run(self, log: &Logger) -> Result<()> {
+        let workers: Vec<_> = (1..self.server.threads)
+            .map(|_| {
+                let gateway = self.clone();
+                let log = log.clone();
+                thread::spawn(move || gateway.serve(&log))
+            })
+            .collect();
+
+        let served = self.serve(log);
+        for worker in workers {
+            worker
+                .join()
+                .unwrap_or_else(|_| Err(KVError::StringError("worker panicked".to_owned())))?;
+        }
+        served
+    }
+
+    fn serve(&self, log: &Logger) -> Result<()> {
+        while !self.server.shutdown.load(Ordering::SeqCst) {
+            let mut request = match self.http.recv_timeout(POLL_INTERVAL)? {
+                Some(request) => request,
+                None => continue,
+            };
+
+            let reply = self.handle(&mut request).unwrap_or_else(|e| error_reply(&e));
+            debug!(log, "{} {} {}", request.method(), request.url(), reply.status);
+            if let Err(e) = request.respond(reply.into_response()) {
+                warn!(log, "HTTP response failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&self, request: &mut Request) -> Result<Reply> {
+        let (path, query) = match request.url().find('?') {
+            Some(i) => (request.url()[..i].to_owned(), Some(request.url()[i + 1..].to_owned())),
+            None => (request.url().to_owned(), None),
+        };
+
+        let method = request.method().clone();
+        if path == "/keys" {
+            return match method {
+                Method::Get => self.scan(query.as_deref().unwrap_or("")),
+                _ => Ok(method_not_allowed()),
+            };
+        }
+        if path == "/batch" {
+            return match method {
+                Method::Post => self.batch(&read_body(request)?),
+                _ => Ok(method_not_allowed()),
+            };
+        }
+
+        let key = match path.strip_prefix("/keys/") {
+            Some(key) if !key.is_empty() => percent_decode(key)?,
+            _ => return Ok(Reply::error(404, ErrorCode::NotFound, "no such resource")),
+        };
+        match method {
+            Method::Get => self.get(request, key),
+            Method::Put => self.put(request, key),
+            Method::Delete => self.delete(request, key),
+            _ => Ok(method_not_allowed()),
+        }
+    }
+
+    fn get(&self, request: &Request, key: String) -> Result<Reply> {
+        let val = match self.server.engine.get(key.to_owned())? {
+            Some(val) => val,
+            None => return Err(KVError::NotFound(format!("key {} not found", key))),
+        };
+
+        let etag = etag(&val);
+        let accept = header_value(request, "Accept").unwrap_or("");
+        let mut reply = if val.starts_with(BINARY_TAG) {
+            if accept.contains(JSON) && !accept.contains(BINARY) {
+                return Err(binary_value(&key));
+            }
+            let bytes = base64::decode(&val[BINARY_TAG.len()..])
+                .map_err(|_| KVError::InvalidValue(format!("{} holds invalid base64", key)))?;
+            Reply::new(200, BINARY, bytes)
+        } else if accept.contains(BINARY) {
+            Reply::new(200, BINARY, val.into_bytes())
+        } else if accept.contains(JSON) {
+            Reply::json(200, &KVPair::new(key, val))?
+        } else {
+            Reply::new(200, TEXT, val.into_bytes())
+        };
+        reply.etag = Some(etag);
+        Ok(reply)
+    }
+
+    fn put(&self, request: &mut Request, key: String) -> Result<Reply> {
+        let body = read_body(request)?;
+        let content_type = header_value(request, "Content-Type").unwrap_or(TEXT);
+        let val = if content_type.starts_with(BINARY) {
+            format!("{}{}", BINARY_TAG, base64::encode(&body))
+        } else {
+            let val: String = if content_type.starts_with(JSON) {
+                serde_json::from_slice(&body).map_err(|_| {
+                    KVError::InvalidValue("a JSON body must be a string".to_owned())
+                })?
+            } else {
+                String::from_utf8(body).map_err(|_| {
+                    KVError::InvalidValue(format!("binary values must be sent as {}", BINARY))
+                })?
+            };
+            check_text(&val)?;
+            val
+        };
+
+        self.server.writable()?;
+        let _writes = self.server.lock_writes();
+        self.check_preconditions(request, &key)?;
+        self.server.set_locked(key, val.to_owned())?;
+
+        let mut reply = Reply::empty(204);
+        reply.etag = Some(etag(&val));
+        Ok(reply)
+    }
+
+    fn delete(&self, request: &Request, key: String) -> Result<Reply> {
+        self.server.writable()?;
+        let _writes = self.server.lock_writes();
+        self.check_preconditions(request, &key)?;
+        self.server.remove_locked(key)?;
+        Ok(Reply::empty(204))
+    }
+
+    /// Checks `If-Match` and `If-None-Match: *` against the current value of
+    /// `key`.
+    fn check_preconditions(&self, request: &Request, key: &str) -> Result<()> {
+        let if_match = header_value(request, "If-Match");
+        let if_none_match = header_value(request, "If-None-Match");
+        if if_match.is_none() && if_none_match.is_none() {
+            return Ok(());
+        }
+
+        let current = self.server.engine.get(key.to_owned())?.map(|val| etag(&val));
+        let matches = |tags: &str| match &current {
+            Some(current) => tags.split(',').any(|tag| tag.trim() == "*" || tag.trim() == current),
+            None => false,
+        };
+        if let Some(tags) = if_match {
+            if !matches(tags) {
+                return Err(KVError::PreconditionFailed(format!("{} does not match", key)));
+            }
+        }
+        if let Some(tags) = if_none_match {
+            if matches(tags) {
+                return Err(KVError::PreconditionFailed(format!("{} exists", key)));
+            }
+        }
+        Ok(())
+    }
+
+    fn scan(&self, query: &str) -> Result<Reply> {
+        let mut prefix = String::new();
+        for param in query.split('&') {
+            if let Some(val) = param.strip_prefix("prefix=") {
+                prefix = percent_decode(&val.replace('+', " "))?;
+            }
+        }
+        Reply::json(200, &self.server.engine.scan(&prefix)?)
+    }
+
+    fn batch(&self, body: &[u8]) -> Result<Reply> {
+        let ops: Vec<BatchOp> = serde_json::from_slice(body)
+            .map_err(|e| KVError::InvalidArgument(format!("invalid batch: {}", e)))?;
+
+        let results: Vec<_> = ops
+            .into_iter()
+            .map(|op| {
+                let executed = match op {
+                    BatchOp::Get { key } => self.server.engine.get(key.to_owned()).and_then(|val| {
+                        match val {
+                            Some(ref val) if val.starts_with(BINARY_TAG) => Err(binary_value(&key)),
+                            Some(val) => Ok((200, Some(val))),
+                            None => Err(KVError::NotFound(format!("key {} not found", key))),
+                        }
+                    }),
+                    BatchOp::Set { key, value } => self
+                        .server
+                        .writable()
+                        .and_then(|_| check_text(&value))
+                        .and_then(|_| self.server.set(key, value))
+                        .map(|_| (204, None)),
+                    BatchOp::Delete { key } => self
+                        .server
+                        .writable()
+                        .and_then(|_| self.server.remove(key))
+                        .map(|_| (204, None)),
+                };
+                match executed {
+                    Ok((status, value)) => BatchResult {
+                        status,
+                        value,
+                        error: None,
+                    },
+                    Err(e) => BatchResult {
+                        status: status(e.code()),
+                        value: None,
+                        error: Some(e.to_string()),
+                    },
+                }
+            })
+            .collect();
+        Reply::json(200, &results)
+    }
+}
+
+fn read_body(request: &mut Request) -> Result<Vec<u8>> {
+    let too_large = || KVError::TooLarge(format!("bodies are limited to {} bytes", MAX_BODY_LEN));
+    if request.body_length().map_or(false, |len| len as u64 > MAX_BODY_LEN) {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_LEN + 1)
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_BODY_LEN {
+        return Err(too_large());
+    }
+    Ok(body)
+}
+
+/// Refuses text values that would be read back as binary ones.
+fn check_text(val: &str) -> Result<()> {
+    if val.starts_with(BINARY_TAG) {
+        return Err(KVError::InvalidValue(format!(
+            "text values may not start with {:?}",
+            BINARY_TAG
+        )));
+    }
+    Ok(())
+}
+
+fn header_value<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+fn header(name: &str, val: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), val.as_bytes()).expect("invalid header")
+}
+
+/// Quoted FNV-1a hash of `val`, stable across restarts.
+fn etag(val: &str) -> String {
+    let hash = val.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("\"{:016x}\"", hash)
+}
+
+fn percent_decode(s: &str) -> Result<String> {
+    let invalid = || KVError::InvalidKey(format!("invalid percent-encoding in {}", s));
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut input = s.bytes();
+    while let Some(byte) = input.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = [input.next().ok_or_else(invalid)?, input.next().ok_or_else(invalid)?];
+        let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+        bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+fn status(code: ErrorCode) -> u16 {
+    match code {
+        ErrorCode::NotFound => 404,
+        ErrorCode::PreconditionFailed => 412,
+        ErrorCode::WrongType => 406,
+        ErrorCode::ReadOnly => 403,
+        ErrorCode::InvalidArgument | ErrorCode::Protocol | ErrorCode::UnsupportedVersion => 400,
+        ErrorCode::Internal | ErrorCode::Unknown => 500,
+    }
+}
+
+fn error_reply(error: &KVError) -> Reply {
+    let code = error.code();
+    let status = match error {
+        KVError::TooLarge(_) => 413,
+        _ => status(code),
+    };
+    Reply::error(status, code, &error.to_string())
+}
+
+fn binary_value(key: &str) -> KVError {
+    KVError::WrongType(format!("{} holds a binary value, answered as {} only", key, BINARY))
+}
+
+fn method_not_allowed() -> Reply {
+    Reply::error(405, ErrorCode::InvalidArgument, "method not allowed")
+}
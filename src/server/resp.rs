@@ -0,0 +1,589 @@
+use slog::Logger;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{KVServer, IO_TIMEOUT, POLL_INTERVAL};
+use crate::common_struct::ErrorCode;
+use crate::engines::KVEngine;
+use crate::error::{KVError, Result};
+use crate::transport::Stream;
+
+const MAX_ARGS: usize = 1024 * 1024;
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+const MAX_INLINE_LEN: usize = 64 * 1024;
+// declared lengths are only trusted this far before the data arrives
+const PREALLOC_ARGS: usize = 1024;
+const PREALLOC_BYTES: usize = 64 * 1024;
+const DEFAULT_SCAN_COUNT: usize = 10;
+const MAX_SCAN_CURSORS: usize = 1024;
+
+/// Commands understood in RESP mode.
+const COMMANDS: &[&str] = &[
+    "PING", "GET", "SET", "DEL", "EXISTS", "INCR", "MGET", "MSET", "SCAN", "INFO", "COMMAND",
+    "QUIT",
+];
+
+/// State shared by the RESP connections of a server.
+#[derive(Default)]
+pub(super) struct RespState {
+    /// Deadlines set by `SET ... EX`, kept in memory only and changed by
+    /// holders of the server write lock only.
+    expiries: Mutex<HashMap<String, Instant>>,
+    cursors: Mutex<ScanCursors>,
+    connections: AtomicUsize,
+}
+
+/// Keys the `SCAN` cursors handed out resume after, the oldest cursors
+/// being forgotten past `MAX_SCAN_CURSORS`.
+#[derive(Default)]
+struct ScanCursors {
+    last: u64,
+    after: BTreeMap<u64, String>,
+}
+
+impl ScanCursors {
+    fn insert(&mut self, key: String) -> u64 {
+        // 0 stands for the start and the end of a scan
+        self.last += 1;
+        self.after.insert(self.last, key);
+        while self.after.len() > MAX_SCAN_CURSORS {
+            let oldest = *self.after.keys().next().unwrap();
+            self.after.remove(&oldest);
+        }
+        self.last
+    }
+}
+
+impl RespState {
+    /// Number of RESP connections being served.
+    pub(super) fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Reply::Simple(status) => write!(writer, "+{}\r\n", status),
+            Reply::Error(message) => write!(writer, "-{}\r\n", message),
+            Reply::Integer(n) => write!(writer, ":{}\r\n", n),
+            Reply::Bulk(None) => writer.write_all(b"$-1\r\n"),
+            Reply::Bulk(Some(val)) => write!(writer, "${}\r\n{}\r\n", val.len(), val),
+            Reply::Array(items) => {
+                write!(writer, "*{}\r\n", items.len())?;
+                items.iter().try_for_each(|item| item.write(writer))
+            }
+        }
+    }
+}
+
+/// How `SET` treats an existing key.
+#[derive(PartialEq)]
+enum SetCondition {
+    Always,
+    IfMissing,
+    IfExists,
+}
+
+impl<E: KVEngine> KVServer<E> {
+    /// Answers the RESP2 commands of a client, such as `redis-cli`, from a
+    /// new thread until it leaves or a shutdown is requested.
+    pub(super) fn spawn_resp_connection(&self, stream: Stream, peer: String, log: &Logger)
+    where
+        E: Send + 'static,
+    {
+        // counted right away, so that a shutdown waits for the connection
+        if self.resp.connections.fetch_add(1, Ordering::SeqCst) >= self.max_clients {
+            self.resp.connections.fetch_sub(1, Ordering::SeqCst);
+            let reply = Reply::Error("ERR max number of clients reached".to_owned());
+            if let Err(e) = reply.write(&mut &stream).and_then(|_| (&stream).flush()) {
+                warn!(log, "Connection from {} failed: {}", peer, e);
+            }
+            return;
+        }
+        let server = self.clone();
+        let log = log.clone();
+        thread::spawn(move || {
+            if let Err(e) = server.serve_resp(&stream) {
+                warn!(log, "Connection from {} failed: {}", peer, e);
+            }
+            // the engine clone is released before `run` returns
+            let resp = Arc::clone(&server.resp);
+            drop(server);
+            resp.connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    fn serve_resp(&self, stream: &Stream) -> Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut writer = BufWriter::new(stream);
+
+        while self.wait_for_command(stream, &mut reader)? {
+            let args = match read_command(&mut reader) {
+                Ok(Some(args)) => args,
+                Ok(None) => break,
+                Err(KVError::Protocol(message)) => {
+                    // the stream cannot be resynchronized
+                    Reply::Error(format!("ERR Protocol error: {}", message)).write(&mut writer)?;
+                    writer.flush()?;
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            if args.is_empty() {
+                continue;
+            }
+
+            let quit = args[0].eq_ignore_ascii_case("QUIT");
+            self.execute_command(&args).write(&mut writer)?;
+            // pipelined commands are answered at once
+            if quit || reader.buffer().is_empty() {
+                writer.flush()?;
+            }
+            if quit {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the next command, polling so that a shutdown closes idle
+    /// connections. Returns `false` once a shutdown is requested.
+    fn wait_for_command(
+        &self,
+        stream: &Stream,
+        reader: &mut BufReader<&Stream>,
+    ) -> Result<bool> {
+        if !reader.buffer().is_empty() {
+            return Ok(true);
+        }
+
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let ready = loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                break false;
+            }
+            match reader.fill_buf() {
+                // the end of the stream as well
+                Ok(_) => break true,
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e.into()),
+            }
+        };
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        Ok(ready)
+    }
+
+    fn execute_command(&self, args: &[String]) -> Reply {
+        let name = args[0].to_ascii_uppercase();
+        let executed = match (name.as_str(), &args[1..]) {
+            ("PING", []) => Ok(Reply::Simple("PONG")),
+            ("PING", [message]) => Ok(Reply::Bulk(Some(message.to_owned()))),
+            ("GET", [key]) => self.resp_get(key).map(Reply::Bulk),
+            ("SET", [key, val, options @ ..]) => self.resp_set(key, val, options),
+            ("DEL", keys) if !keys.is_empty() => self.resp_del(keys),
+            ("EXISTS", keys) if !keys.is_empty() => self.resp_exists(keys),
+            ("INCR", [key]) => self.resp_incr(key),
+            ("MGET", keys) if !keys.is_empty() => self.resp_mget(keys),
+            ("MSET", pairs) if !pairs.is_empty() && pairs.len() % 2 == 0 => self.resp_mset(pairs),
+            ("SCAN", [cursor, options @ ..]) => self.resp_scan(cursor, options),
+            ("INFO", []) | ("INFO", [_]) => Ok(Reply::Bulk(Some(self.resp_info()))),
+            // redis-cli asks for the command table when it starts
+            ("COMMAND", _) => Ok(Reply::Array(Vec::new())),
+            ("QUIT", []) => Ok(Reply::Simple("OK")),
+            (name, _) if COMMANDS.contains(&name) => Err(KVError::InvalidArgument(format!(
+                "wrong number of arguments for '{}' command",
+                name.to_lowercase()
+            ))),
+            _ => Err(KVError::InvalidArgument(format!("unknown command '{}'", args[0]))),
+        };
+
+        executed.unwrap_or_else(error_reply)
+    }
+
+    fn resp_get(&self, key: &str) -> Result<Option<String>> {
+        if self.expired(key)? {
+            return Ok(None);
+        }
+        self.engine.get(key.to_owned())
+    }
+
+    /// `SET key value [EX seconds|PX milliseconds] [NX|XX]`
+    fn resp_set(&self, key: &str, val: &str, options: &[String]) -> Result<Reply> {
+        self.writable()?;
+
+        let mut ttl = None;
+        let mut condition = SetCondition::Always;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_str() {
+                "EX" | "PX" if ttl.is_none() => {
+                    let amount: u64 = match options.next().map(|amount| amount.parse()) {
+                        Some(Ok(amount)) if amount > 0 => amount,
+                        _ => return Err(invalid_expire_time()),
+                    };
+                    ttl = Some(if option.eq_ignore_ascii_case("EX") {
+                        Duration::from_secs(amount)
+                    } else {
+                        Duration::from_millis(amount)
+                    });
+                }
+                "NX" if condition == SetCondition::Always => condition = SetCondition::IfMissing,
+                "XX" if condition == SetCondition::Always => condition = SetCondition::IfExists,
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        // plain writes of keys without expiry only wait for conditional ones
+        if ttl.is_none() && condition == SetCondition::Always {
+            let _writes = self.writes.read().unwrap();
+            if !self.expiries().contains_key(key) {
+                self.set_locked(key.to_owned(), val.to_owned())?;
+                return Ok(Reply::Simple("OK"));
+            }
+        }
+
+        let _writes = self.lock_writes();
+        if condition != SetCondition::Always {
+            let exists = !self.expire_locked(key)? && self.engine.get(key.to_owned())?.is_some();
+            if exists != (condition == SetCondition::IfExists) {
+                return Ok(Reply::Bulk(None));
+            }
+        }
+
+        self.set_locked(key.to_owned(), val.to_owned())?;
+        match ttl {
+            Some(ttl) => self.expiries().insert(key.to_owned(), Instant::now() + ttl),
+            None => self.expiries().remove(key),
+        };
+        Ok(Reply::Simple("OK"))
+    }
+
+    fn resp_del(&self, keys: &[String]) -> Result<Reply> {
+        self.writable()?;
+
+        let _writes = self.lock_writes();
+        let mut removed = 0;
+        for key in keys {
+            if self.expire_locked(key)? {
+                continue;
+            }
+            match self.remove_locked(key.to_owned()) {
+                Ok(()) => removed += 1,
+                Err(ref e) if e.code() == ErrorCode::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            self.expiries().remove(key);
+        }
+        Ok(Reply::Integer(removed))
+    }
+
+    fn resp_exists(&self, keys: &[String]) -> Result<Reply> {
+        let mut found = 0;
+        for key in keys {
+            if self.resp_get(key)?.is_some() {
+                found += 1;
+            }
+        }
+        Ok(Reply::Integer(found))
+    }
+
+    fn resp_incr(&self, key: &str) -> Result<Reply> {
+        self.writable()?;
+
+        let _writes = self.lock_writes();
+        let current = if self.expire_locked(key)? {
+            None
+        } else {
+            self.engine.get(key.to_owned())?
+        };
+        let current: i64 = match current {
+            Some(val) => val.parse().map_err(|_| {
+                KVError::InvalidArgument("value is not an integer or out of range".to_owned())
+            })?,
+            None => 0,
+        };
+        let next = current.checked_add(1).ok_or_else(|| {
+            KVError::InvalidArgument("increment or decrement would overflow".to_owned())
+        })?;
+
+        // like Redis, the key keeps its expiry
+        self.set_locked(key.to_owned(), next.to_string())?;
+        Ok(Reply::Integer(next))
+    }
+
+    fn resp_mget(&self, keys: &[String]) -> Result<Reply> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(Reply::Bulk(self.resp_get(key)?));
+        }
+        Ok(Reply::Array(values))
+    }
+
+    fn resp_mset(&self, pairs: &[String]) -> Result<Reply> {
+        self.writable()?;
+
+        let _writes = self.lock_writes();
+        for pair in pairs.chunks(2) {
+            self.set_locked(pair[0].to_owned(), pair[1].to_owned())?;
+            self.expiries().remove(&pair[0]);
+        }
+        Ok(Reply::Simple("OK"))
+    }
+
+    /// `SCAN cursor [MATCH pattern] [COUNT count]`, each cursor standing for
+    /// the last key looked at so that keys written meanwhile do not shift
+    /// the next pages, which the engine resumes reading from.
+    fn resp_scan(&self, cursor: &str, options: &[String]) -> Result<Reply> {
+        let invalid_cursor = || KVError::InvalidArgument("invalid cursor".to_owned());
+        let after = match cursor.parse::<u64>().map_err(|_| invalid_cursor())? {
+            0 => None,
+            id => {
+                let cursors = self.resp.cursors.lock().unwrap();
+                Some(cursors.after.get(&id).cloned().ok_or_else(invalid_cursor)?)
+            }
+        };
+
+        let mut pattern = None;
+        let mut count = DEFAULT_SCAN_COUNT;
+        for option in options.chunks(2) {
+            match (option[0].to_ascii_uppercase().as_str(), option.get(1)) {
+                ("MATCH", Some(glob)) => pattern = Some(glob.chars().collect::<Vec<_>>()),
+                ("COUNT", Some(n)) => match n.parse() {
+                    Ok(n) if n > 0 => count = n,
+                    _ => return Err(syntax_error()),
+                },
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        // only the literal start of the pattern narrows the engine scan
+        let prefix: String = match &pattern {
+            Some(pattern) => pattern.iter().take_while(|c| !"*?\\".contains(**c)).collect(),
+            None => String::new(),
+        };
+        // like Redis, a call looks at COUNT keys at most, so a page may hold
+        // fewer matches, or none, before the scan ends; one key past them
+        // tells whether another page follows
+        let mut pairs = self.engine.scan_from(&prefix, after.as_deref(), count + 1)?;
+        let more = pairs.len() > count;
+        pairs.truncate(count);
+        let next = match pairs.last() {
+            Some(last) if more => self.resp.cursors.lock().unwrap().insert(last.key.to_owned()),
+            _ => 0,
+        };
+
+        let mut page = Vec::new();
+        for pair in pairs {
+            let matches = match &pattern {
+                Some(pattern) => glob_match(pattern, &pair.key.chars().collect::<Vec<_>>()),
+                None => true,
+            };
+            if matches && !self.expired(&pair.key)? {
+                page.push(Reply::Bulk(Some(pair.key)));
+            }
+        }
+        Ok(Reply::Array(vec![Reply::Bulk(Some(next.to_string())), Reply::Array(page)]))
+    }
+
+    fn resp_info(&self) -> String {
+        let mut info = String::from("# Server\r\n");
+        info.push_str(&format!("kvs_version:{}\r\n", env!("CARGO_PKG_VERSION")));
+        info.push_str(&format!("read_only:{}\r\n", self.read_only as u8));
+        info.push_str("\r\n# Clients\r\n");
+        info.push_str(&format!("connected_clients:{}\r\n", self.resp.connections()));
+        let stats: BTreeMap<String, u64> = self.engine.stats().into_iter().collect();
+        if !stats.is_empty() {
+            info.push_str("\r\n# Stats\r\n");
+            for (name, val) in stats {
+                info.push_str(&format!("{}:{}\r\n", name, val));
+            }
+        }
+        info
+    }
+
+    fn expiries(&self) -> MutexGuard<'_, HashMap<String, Instant>> {
+        self.resp.expiries.lock().unwrap()
+    }
+
+    /// Whether `key` is past its expiry, removing it from the engine if so.
+    fn expired(&self, key: &str) -> Result<bool> {
+        if !self.past_deadline(key) {
+            return Ok(false);
+        }
+        let _writes = self.lock_writes();
+        self.expire_locked(key)
+    }
+
+    /// `expired` for callers holding the write lock exclusively.
+    fn expire_locked(&self, key: &str) -> Result<bool> {
+        // the key may have been written again before the lock was taken
+        if !self.past_deadline(key) {
+            return Ok(false);
+        }
+
+        self.expiries().remove(key);
+        match self.remove_locked(key.to_owned()) {
+            Ok(()) => Ok(true),
+            Err(ref e) if e.code() == ErrorCode::NotFound => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn past_deadline(&self, key: &str) -> bool {
+        match self.expiries().get(key) {
+            Some(deadline) => *deadline <= Instant::now(),
+            None => false,
+        }
+    }
+}
+
+/// Next command, either an array of bulk strings or an inline command as
+/// typed in telnet. `None` once the client closed the connection.
+fn read_command<R: BufRead>(reader: &mut R) -> Result<Option<Vec<String>>> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    if !line.starts_with('*') {
+        return Ok(Some(line.split_whitespace().map(str::to_owned).collect()));
+    }
+
+    let len = parse_len(&line[1..], MAX_ARGS)?;
+    let mut args = Vec::with_capacity(len.min(PREALLOC_ARGS));
+    for _ in 0..len {
+        let header =
+            read_line(reader)?.ok_or_else(|| protocol_error("unexpected end of stream"))?;
+        if !header.starts_with('$') {
+            return Err(protocol_error(&format!("expected '$', got '{}'", header)));
+        }
+
+        let len = parse_len(&header[1..], MAX_BULK_LEN)?;
+        // the buffer grows with the data actually read
+        let mut buf = Vec::with_capacity((len + 2).min(PREALLOC_BYTES));
+        reader.by_ref().take(len as u64 + 2).read_to_end(&mut buf)?;
+        if buf.len() < len + 2 {
+            return Err(protocol_error("unexpected end of stream"));
+        }
+        if !buf.ends_with(b"\r\n") {
+            return Err(protocol_error("bulk string not terminated by CRLF"));
+        }
+        buf.truncate(len);
+        let arg = String::from_utf8(buf).map_err(|_| protocol_error("invalid UTF-8 argument"))?;
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// Line without its CRLF, `None` at the end of the stream.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>> {
+    // room for the CRLF
+    let limit = MAX_INLINE_LEN as u64 + 2;
+    let mut line = Vec::new();
+    if reader.by_ref().take(limit).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.len() as u64 == limit && line.last() != Some(&b'\n') {
+        return Err(protocol_error("too big inline request"));
+    }
+    while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    let line = String::from_utf8(line).map_err(|_| protocol_error("invalid UTF-8 line"))?;
+    Ok(Some(line))
+}
+
+fn parse_len(len: &str, max: usize) -> Result<usize> {
+    match len.parse() {
+        Ok(len) if len <= max => Ok(len),
+        _ => Err(protocol_error(&format!("invalid length '{}'", len))),
+    }
+}
+
+/// Whether `key` matches the glob `pattern`, made of literal characters,
+/// `*`, `?` and `\` escapes.
+///
+/// On a mismatch, the last `*` seen takes one more character and matching
+/// resumes after it, which never needs to revisit an earlier `*`.
+fn glob_match(pattern: &[char], key: &[char]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    // position of the last `*` in the pattern, and of the key character
+    // following what it took
+    let mut star = None;
+    while k < key.len() {
+        match pattern.get(p) {
+            Some(&'*') => {
+                star = Some((p, k));
+                p += 1;
+                continue;
+            }
+            Some(&'?') => {
+                p += 1;
+                k += 1;
+                continue;
+            }
+            Some(&'\\') if p + 1 < pattern.len() => {
+                if pattern[p + 1] == key[k] {
+                    p += 2;
+                    k += 1;
+                    continue;
+                }
+            }
+            Some(c) => {
+                if *c == key[k] {
+                    p += 1;
+                    k += 1;
+                    continue;
+                }
+            }
+            None => {}
+        }
+        match star {
+            Some((star_p, star_k)) => {
+                p = star_p + 1;
+                k = star_k + 1;
+                star = Some((star_p, k));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn error_reply(error: KVError) -> Reply {
+    let message = error.to_string().replace(|c| c == '\r' || c == '\n', " ");
+    match error.code() {
+        ErrorCode::ReadOnly => {
+            Reply::Error("READONLY You can't write against a read only server.".to_owned())
+        }
+        ErrorCode::WrongType => Reply::Error(format!("WRONGTYPE {}", message)),
+        _ => Reply::Error(format!("ERR {}", message)),
+    }
+}
+
+fn protocol_error(message: &str) -> KVError {
+    KVError::Protocol(message.to_owned())
+}
+
+fn syntax_error() -> KVError {
+    KVError::InvalidArgument("syntax error".to_owned())
+}
+
+fn invalid_expire_time() -> KVError {
+    KVError::InvalidArgument("invalid expire time in 'set' command".to_owned())
+}
@@ -0,0 +1,144 @@
+use rustls::internal::pemfile;
+use rustls::{
+    AllowAnyAuthenticatedClient, Certificate, ClientConfig, NoClientAuth, PrivateKey,
+    RootCertStore, ServerCertVerified, ServerCertVerifier, ServerConfig, TLSError,
+};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use webpki::DNSNameRef;
+
+use crate::error::{KVError, Result};
+
+/// TLS settings of a `KVServer`, read from PEM files.
+#[derive(Clone)]
+pub struct ServerTls(pub(crate) Arc<ServerConfig>);
+
+impl ServerTls {
+    /// Presents the certificate chain in `cert`, signed with the private key
+    /// in `key`, to clients.
+    pub fn from_pem(cert: &Path, key: &Path) -> Result<Self> {
+        ServerTls::build(cert, key, None)
+    }
+
+    /// Also requires clients to present a certificate issued by one of the
+    /// CAs in `client_ca`, rejecting connections from any other client.
+    pub fn with_client_auth(cert: &Path, key: &Path, client_ca: &Path) -> Result<Self> {
+        ServerTls::build(cert, key, Some(client_ca))
+    }
+
+    fn build(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<Self> {
+        let verifier = match client_ca {
+            Some(ca) => AllowAnyAuthenticatedClient::new(read_roots(ca)?),
+            None => NoClientAuth::new(),
+        };
+        let mut config = ServerConfig::new(verifier);
+        config.set_single_cert(read_certs(cert)?, read_key(key)?).map_err(tls_error)?;
+        Ok(ServerTls(Arc::new(config)))
+    }
+}
+
+/// TLS settings of a `KVClient`.
+#[derive(Clone)]
+pub struct ClientTls {
+    pub(crate) config: Arc<ClientConfig>,
+    pub(crate) insecure: bool,
+}
+
+impl ClientTls {
+    /// Trusts the servers whose certificate is issued by one of the CAs in
+    /// `ca`, and whose name matches the host connected to.
+    pub fn from_ca(ca: &Path) -> Result<Self> {
+        let mut config = ClientConfig::new();
+        config.root_store = read_roots(ca)?;
+        Ok(ClientTls {
+            config: Arc::new(config),
+            insecure: false,
+        })
+    }
+
+    /// Trusts any server, which defeats the point of TLS outside of
+    /// development setups.
+    pub fn insecure() -> Self {
+        let mut config = ClientConfig::new();
+        config.dangerous().set_certificate_verifier(Arc::new(AcceptAnyServerCert));
+        ClientTls {
+            config: Arc::new(config),
+            insecure: true,
+        }
+    }
+
+    /// Presents the certificate chain in `cert`, signed with the private key
+    /// in `key`, to servers requiring client certificates.
+    pub fn identity(mut self, cert: &Path, key: &Path) -> Result<Self> {
+        Arc::make_mut(&mut self.config)
+            .set_single_client_cert(read_certs(cert)?, read_key(key)?)
+            .map_err(tls_error)?;
+        Ok(self)
+    }
+
+    /// Name the certificate of the server at `host` is checked against.
+    /// IP addresses are not supported, except by insecure clients which
+    /// check nothing.
+    pub(crate) fn server_name<'a>(&self, host: &'a str) -> Result<DNSNameRef<'a>> {
+        match DNSNameRef::try_from_ascii_str(host) {
+            Ok(name) => Ok(name),
+            Err(_) if self.insecure => Ok(DNSNameRef::try_from_ascii_str("localhost").unwrap()),
+            Err(_) => Err(KVError::Tls(format!(
+                "{} is not a host name the server certificate can be checked against",
+                host
+            ))),
+        }
+    }
+}
+
+struct AcceptAnyServerCert;
+
+impl ServerCertVerifier for AcceptAnyServerCert {
+    fn verify_server_cert(
+        &self,
+        _: &RootCertStore,
+        _: &[Certificate],
+        _: DNSNameRef,
+        _: &[u8],
+    ) -> std::result::Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let certs = pemfile::certs(&mut BufReader::new(File::open(path)?))
+        .map_err(|_| pem_error(path, "certificates"))?;
+    if certs.is_empty() {
+        return Err(pem_error(path, "certificates"));
+    }
+    Ok(certs)
+}
+
+/// First PKCS#8 or RSA private key found in `path`.
+fn read_key(path: &Path) -> Result<PrivateKey> {
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?))
+        .map_err(|_| pem_error(path, "private key"))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(path)?))
+            .map_err(|_| pem_error(path, "private key"))?;
+    }
+    keys.into_iter().next().ok_or_else(|| pem_error(path, "private key"))
+}
+
+fn read_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(path)? {
+        roots.add(&cert).map_err(|e| KVError::Tls(format!("{}: {:?}", path.display(), e)))?;
+    }
+    Ok(roots)
+}
+
+fn pem_error(path: &Path, what: &str) -> KVError {
+    KVError::Tls(format!("no valid PEM {} in {}", what, path.display()))
+}
+
+fn tls_error(e: TLSError) -> KVError {
+    KVError::Tls(e.to_string())
+}
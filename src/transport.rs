@@ -0,0 +1,278 @@
+//! Connections over TCP or, on Unix, over Unix domain sockets named by a
+//! `unix:PATH` address, either of them possibly encrypted with TLS.
+
+use rustls::{ClientSession, ServerSession, StreamOwned};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::addr::ServerAddr;
+#[cfg(not(unix))]
+use crate::error::KVError;
+use crate::error::Result;
+use crate::tls::{ClientTls, ServerTls};
+
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix { listener: UnixListener, path: PathBuf },
+}
+
+impl Listener {
+    /// Binds every address of the comma-separated list `addrs`, a host
+    /// name being bound on each of its addresses. Unix sockets are given the
+    /// permissions in `mode` and removed once their listener is dropped.
+    pub fn bind_all(addrs: &str, mode: Option<u32>) -> Result<Vec<Self>> {
+        let mut listeners = Vec::new();
+        for addr in ServerAddr::parse_list(addrs)? {
+            match &addr {
+                ServerAddr::Unix(path) => listeners.push(bind_unix(path, mode)?),
+                ServerAddr::Tcp { .. } => {
+                    for socket_addr in addr.resolve()? {
+                        // the error names the address, as a host may have several
+                        let listener = TcpListener::bind(socket_addr).map_err(|e| {
+                            io::Error::new(e.kind(), format!("{}: {}", socket_addr, e))
+                        })?;
+                        listeners.push(Listener::Tcp(listener));
+                    }
+                }
+            }
+        }
+        Ok(listeners)
+    }
+
+    /// Where to connect to wake a thread blocked in `accept`.
+    pub fn waker(&self) -> io::Result<Waker> {
+        match self {
+            Listener::Tcp(listener) => {
+                let mut addr = listener.local_addr()?;
+                // a wildcard address is reached through the loopback one
+                if addr.ip().is_unspecified() {
+                    match addr {
+                        SocketAddr::V4(_) => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+                        SocketAddr::V6(_) => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+                    }
+                }
+                Ok(Waker::Tcp(addr))
+            }
+            #[cfg(unix)]
+            Listener::Unix { path, .. } => Ok(Waker::Unix(path.to_owned())),
+        }
+    }
+
+    /// Accepts a connection along with a description of its peer.
+    pub fn accept(&self) -> io::Result<(Stream, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept()?;
+                Ok((Stream::Tcp(stream), peer.to_string()))
+            }
+            #[cfg(unix)]
+            Listener::Unix { listener, path } => {
+                // clients of a Unix socket are usually unnamed
+                let (stream, _) = listener.accept()?;
+                Ok((Stream::Unix(stream), format!("unix:{}", path.display())))
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            if let Listener::Unix { path, .. } = self {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: Option<u32>) -> Result<Listener> {
+    // a socket left behind by a server that died is replaced, a live one or
+    // any other file is not
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() || UnixStream::connect(path).is_ok() {
+            let message = format!("{} is in use", path.display());
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, message).into());
+        }
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    let listener = Listener::Unix {
+        listener,
+        path: path.to_owned(),
+    };
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: &Path, _: Option<u32>) -> Result<Listener> {
+    Err(unsupported(path))
+}
+
+/// Address of a listener, connected to in order to wake the thread blocked
+/// accepting its connections.
+#[derive(Clone)]
+pub(crate) enum Waker {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Waker {
+    pub fn wake(&self) {
+        // the woken thread drops the connection unanswered
+        let _ = match self {
+            Waker::Tcp(addr) => TcpStream::connect_timeout(addr, WAKE_TIMEOUT).map(drop),
+            #[cfg(unix)]
+            Waker::Unix(path) => UnixStream::connect(path).map(drop),
+        };
+    }
+}
+
+// sessions need `&mut` access, which the reading and writing halves of a
+// stream share
+type TlsStream<S> = Arc<Mutex<StreamOwned<S, Stream>>>;
+
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    TlsServer(TlsStream<ServerSession>),
+    TlsClient(TlsStream<ClientSession>),
+}
+
+impl Stream {
+    /// Connects to `addr`, trying each address a host name resolves to, then
+    /// speaks TLS if `tls` is given. The handshake takes place on the first
+    /// read or write.
+    pub fn connect(addr: &str, tls: Option<&ClientTls>) -> Result<Self> {
+        let addr = addr.parse::<ServerAddr>()?;
+        let stream = match &addr {
+            #[cfg(unix)]
+            ServerAddr::Unix(path) => Stream::Unix(UnixStream::connect(path)?),
+            #[cfg(not(unix))]
+            ServerAddr::Unix(path) => return Err(unsupported(path)),
+            ServerAddr::Tcp { .. } => Stream::Tcp(TcpStream::connect(&addr.resolve()?[..])?),
+        };
+
+        let tls = match tls {
+            Some(tls) => tls,
+            None => return Ok(stream),
+        };
+        // a Unix socket has no host name to check
+        let host = match &addr {
+            ServerAddr::Tcp { host, .. } => host.as_str(),
+            ServerAddr::Unix(_) => "localhost",
+        };
+        let session = ClientSession::new(&tls.config, tls.server_name(host)?);
+        Ok(Stream::TlsClient(Arc::new(Mutex::new(StreamOwned::new(session, stream)))))
+    }
+
+    /// Speaks TLS over this stream as a server, the handshake taking place on
+    /// the first read or write.
+    pub fn accept_tls(self, tls: &ServerTls) -> Self {
+        let session = ServerSession::new(&tls.0);
+        Stream::TlsServer(Arc::new(Mutex::new(StreamOwned::new(session, self))))
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(stream) => Ok(Stream::Tcp(stream.try_clone()?)),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Ok(Stream::Unix(stream.try_clone()?)),
+            Stream::TlsServer(stream) => Ok(Stream::TlsServer(Arc::clone(stream))),
+            Stream::TlsClient(stream) => Ok(Stream::TlsClient(Arc::clone(stream))),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+            Stream::TlsServer(stream) => stream.lock().unwrap().sock.set_read_timeout(timeout),
+            Stream::TlsClient(stream) => stream.lock().unwrap().sock.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+            Stream::TlsServer(stream) => stream.lock().unwrap().sock.set_write_timeout(timeout),
+            Stream::TlsClient(stream) => stream.lock().unwrap().sock.set_write_timeout(timeout),
+        }
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).read(buf),
+            Stream::TlsServer(stream) => stream.lock().unwrap().read(buf),
+            Stream::TlsClient(stream) => stream.lock().unwrap().read(buf),
+        }
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).write(buf),
+            Stream::TlsServer(stream) => stream.lock().unwrap().write(buf),
+            Stream::TlsClient(stream) => stream.lock().unwrap().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).flush(),
+            Stream::TlsServer(stream) => stream.lock().unwrap().flush(),
+            Stream::TlsClient(stream) => stream.lock().unwrap().flush(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+#[cfg(not(unix))]
+fn unsupported(path: &Path) -> KVError {
+    KVError::InvalidAddress(format!("unix:{} needs Unix domain sockets", path.display()))
+}
@@ -0,0 +1,90 @@
+use kvs::{Result, ServerAddr, DEFAULT_PORT};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+fn tcp(host: &str, port: u16) -> ServerAddr {
+    ServerAddr::Tcp {
+        host: host.to_owned(),
+        port,
+    }
+}
+
+// Should accept IPs, IPv6 in brackets, host names and bare hosts
+#[test]
+fn addr_parse() -> Result<()> {
+    assert_eq!("127.0.0.1:5000".parse::<ServerAddr>()?, tcp("127.0.0.1", 5000));
+    assert_eq!("[::1]:5000".parse::<ServerAddr>()?, tcp("::1", 5000));
+    assert_eq!("localhost:5000".parse::<ServerAddr>()?, tcp("localhost", 5000));
+
+    // the port defaults to 4000
+    assert_eq!("127.0.0.1".parse::<ServerAddr>()?, tcp("127.0.0.1", DEFAULT_PORT));
+    assert_eq!("[::1]".parse::<ServerAddr>()?, tcp("::1", DEFAULT_PORT));
+    assert_eq!("::1".parse::<ServerAddr>()?, tcp("::1", DEFAULT_PORT));
+    assert_eq!("kvs.example.com".parse::<ServerAddr>()?, tcp("kvs.example.com", DEFAULT_PORT));
+
+    assert_eq!(
+        "unix:/run/kvs:1.sock".parse::<ServerAddr>()?,
+        ServerAddr::Unix(PathBuf::from("/run/kvs:1.sock"))
+    );
+
+    Ok(())
+}
+
+// Should explain what is wrong with an address
+#[test]
+fn addr_parse_errors() {
+    for (addr, message) in &[
+        ("127.0.0.1:port", "invalid port port"),
+        ("127.0.0.1:65536", "invalid port 65536"),
+        ("127.0.0.1:", "invalid port "),
+        (":4000", "missing host"),
+        ("[::1", "missing ]"),
+        ("[::1]4000", "unexpected characters after ]"),
+        ("[]:4000", "missing host"),
+        ("unix:", "missing socket path"),
+    ] {
+        let error = addr.parse::<ServerAddr>().unwrap_err().to_string();
+        assert!(error.contains(message), "{}: {}", addr, error);
+    }
+}
+
+// Should write addresses back in a form they parse from
+#[test]
+fn addr_display() -> Result<()> {
+    for addr in &["127.0.0.1:4000", "[::1]:4000", "localhost:4000", "unix:/run/kvs.sock"] {
+        assert_eq!(addr.parse::<ServerAddr>()?.to_string(), *addr);
+    }
+    assert_eq!("::1".parse::<ServerAddr>()?.to_string(), "[::1]:4000");
+
+    Ok(())
+}
+
+// Should split lists of addresses
+#[test]
+fn addr_parse_list() -> Result<()> {
+    let addrs = ServerAddr::parse_list("127.0.0.1:4000, [::1]:4000,unix:/run/kvs.sock")?;
+    assert_eq!(
+        addrs,
+        vec![
+            tcp("127.0.0.1", 4000),
+            tcp("::1", 4000),
+            ServerAddr::Unix(PathBuf::from("/run/kvs.sock")),
+        ]
+    );
+    assert!(ServerAddr::parse_list("127.0.0.1:4000,").is_err());
+
+    Ok(())
+}
+
+// Should resolve IPs and host names to socket addresses
+#[test]
+fn addr_resolve() -> Result<()> {
+    let expected: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+    assert_eq!(tcp("127.0.0.1", 4000).resolve()?, vec![expected]);
+    assert!(tcp("localhost", 4000).resolve()?.iter().all(|addr| addr.port() == 4000));
+
+    assert!(tcp("host.invalid", 4000).resolve().is_err());
+    assert!(ServerAddr::Unix(PathBuf::from("/run/kvs.sock")).resolve().is_err());
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use assert_cmd::prelude::*;
+use kvs::{EngineMeta, KVEngine, KVStore, Result, SledKVEngine};
+use predicates::str::contains;
+use std::process::Command;
+use tempfile::TempDir;
+
+// `kvs-admin migrate` should move every live key to the new engine
+#[test]
+fn admin_migrate_kvs_to_sled() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let data_dir = temp_dir.path().join("data");
+    {
+        let store = KVStore::open(&data_dir)?;
+        for i in 0..100 {
+            store.set(format!("key{}", i), format!("value{}", i))?;
+        }
+        store.remove("key0".to_owned())?;
+    }
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["migrate", "--from", "kvs", "--to", "sled"])
+        .arg(&data_dir)
+        .assert()
+        .success()
+        .stdout(contains("migrated 99 keys"));
+
+    let meta = EngineMeta::read(&data_dir)?.expect("metadata file missing");
+    assert_eq!(meta.engine, "sled");
+    assert!(!temp_dir.path().join("data.migrate-old").exists());
+
+    let store = SledKVEngine::open(&data_dir)?;
+    assert_eq!(store.get("key0".to_owned())?, None);
+    for i in 1..100 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+
+    Ok(())
+}
+
+// `kvs-admin migrate` should refuse a directory owned by another engine
+#[test]
+fn admin_migrate_wrong_source() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(SledKVEngine::open(temp_dir.path())?);
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["migrate", "--from", "kvs", "--to", "lsm"])
+        .arg(temp_dir.path())
+        .assert()
+        .failure()
+        .stderr(contains("sled"));
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+#![cfg(feature = "async")]
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+use common::start_async;
+use kvs::{
+    AsyncKVClient, AsyncKVServer, Codec, KVClient, KVEngine, KVError, KVRequest, KVResponse,
+    MemoryKVEngine, Result, PROTOCOL_VERSION,
+};
+use serde::Deserialize;
+use serde_json::{Deserializer, Value};
+use std::io::Write;
+use std::net;
+use std::thread;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task;
+
+// Should serve the async client
+#[tokio::test(threaded_scheduler)]
+async fn async_client_server() -> Result<()> {
+    let server = start_async(AsyncKVServer::new(MemoryKVEngine::new())).await?;
+    let client = AsyncKVClient::new(server.addr.to_owned());
+
+    client.set("key1".to_owned(), "value1".to_owned()).await?;
+    client.set("key2".to_owned(), "value2".to_owned()).await?;
+    assert_eq!(client.get("key1".to_owned()).await?, Some("value1".to_owned()));
+    client.remove("key1".to_owned()).await?;
+    assert!(client.remove("key1".to_owned()).await.is_err());
+
+    // the blocking client speaks the same protocol
+    let addr = server.addr.to_owned();
+    let value = task::spawn_blocking(move || {
+        let req = KVRequest::Get {
+            key: "key2".to_owned(),
+        };
+        KVClient::new(addr, req)?.connect()
+    })
+    .await
+    .unwrap()?;
+    assert_eq!(value, Some("value2".to_owned()));
+
+    server.stop().await
+}
+
+// Should keep answering while many idle connections are open
+#[tokio::test(threaded_scheduler)]
+async fn async_idle_connections() -> Result<()> {
+    let server = start_async(AsyncKVServer::new(MemoryKVEngine::new())).await?;
+    let client = AsyncKVClient::new(server.addr.to_owned());
+
+    let mut idle = Vec::new();
+    for _ in 0..500 {
+        idle.push(TcpStream::connect(&server.addr).await?);
+    }
+
+    let mut requests = Vec::new();
+    for i in 0..100 {
+        let client = client.clone();
+        requests.push(tokio::spawn(async move {
+            client.set(format!("key{}", i), format!("value{}", i)).await
+        }));
+    }
+    for request in requests {
+        request.await.unwrap()?;
+    }
+    for i in 0..100 {
+        let value = client.get(format!("key{}", i)).await?;
+        assert_eq!(value, Some(format!("value{}", i)));
+    }
+
+    drop(idle);
+    server.stop().await
+}
+
+// Should read JSON requests split across writes, whatever their strings hold
+#[tokio::test(threaded_scheduler)]
+async fn async_split_json() -> Result<()> {
+    let server = start_async(AsyncKVServer::new(MemoryKVEngine::new())).await?;
+    let client = AsyncKVClient::new(server.addr.to_owned());
+    client.set("{\"key\"}".to_owned(), "value1".to_owned()).await?;
+
+    // requests skipping the handshake are JSON
+    let mut stream = TcpStream::connect(&server.addr).await?;
+    let request = br#"{"Get":{"key":"{\"key\"}"}}"#;
+    stream.write_all(&request[..12]).await?;
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+    stream.write_all(&request[12..]).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    match serde_json::from_slice(&response)? {
+        KVResponse::Ok(value) => assert_eq!(value, Some("value1".to_owned())),
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    server.stop().await
+}
+
+// Should answer the connections accepted before a shutdown
+#[tokio::test(threaded_scheduler)]
+async fn async_shutdown_drains() -> Result<()> {
+    let server = start_async(AsyncKVServer::new(MemoryKVEngine::new())).await?;
+
+    let mut stream = TcpStream::connect(&server.addr).await?;
+    let request = br#"{"Set":{"key":"key1","val":"value1"}}"#;
+    stream.write_all(&request[..10]).await?;
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+    let _ = server.stop.send(());
+    stream.write_all(&request[10..]).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    match serde_json::from_slice(&response)? {
+        KVResponse::Ok(_) => {}
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    server.task.await.unwrap()
+}
+
+// Should reject writes in read-only mode
+#[tokio::test(threaded_scheduler)]
+async fn async_read_only() -> Result<()> {
+    let engine = MemoryKVEngine::new();
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    let server = start_async(AsyncKVServer::read_only(engine)).await?;
+    let client = AsyncKVClient::new(server.addr.to_owned());
+
+    assert_eq!(client.get("key1".to_owned()).await?, Some("value1".to_owned()));
+    assert!(client.set("key1".to_owned(), "value2".to_owned()).await.is_err());
+    assert!(client.remove("key1".to_owned()).await.is_err());
+
+    server.stop().await
+}
+
+// Should speak every codec once negotiated
+#[tokio::test(threaded_scheduler)]
+async fn async_codecs() -> Result<()> {
+    let server = start_async(AsyncKVServer::new(MemoryKVEngine::new())).await?;
+
+    for &codec in &[Codec::Json, Codec::MessagePack, Codec::Bincode] {
+        let client = AsyncKVClient::new(server.addr.to_owned()).prefer_codec(codec);
+        let key = format!("{:?}", codec);
+        client.set(key.to_owned(), "value1".to_owned()).await?;
+        assert_eq!(client.get(key.to_owned()).await?, Some("value1".to_owned()));
+        client.remove(key.to_owned()).await?;
+        assert_eq!(client.get(key).await?, None);
+    }
+
+    server.stop().await
+}
+
+// Should refuse servers answering the handshake with a version it does not speak
+#[tokio::test(threaded_scheduler)]
+async fn async_handshake_newer_version() -> Result<()> {
+    let listener = net::TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let server = thread::spawn(move || -> Result<()> {
+        let (mut stream, _) = listener.accept()?;
+        let mut deserializer = Deserializer::from_reader(&stream);
+        Value::deserialize(&mut deserializer)?;
+        let hello = KVResponse::Hello {
+            version: PROTOCOL_VERSION + 1,
+            capabilities: Vec::new(),
+            codec: Codec::Json,
+        };
+        stream.write_all(&serde_json::to_vec(&hello)?)?;
+        Ok(())
+    });
+
+    match AsyncKVClient::new(addr.to_string()).get("key1".to_owned()).await {
+        Err(KVError::UnsupportedProtocol(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    server.join().expect("fake server panicked")
+}
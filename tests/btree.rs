@@ -0,0 +1,109 @@
+use kvs::{BTreeKVEngine, KVEngine, KVStore, Result};
+use tempfile::TempDir;
+
+// Should get previously stored value, also after reopening
+#[test]
+fn btree_get_stored_value() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = BTreeKVEngine::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, None);
+
+    // Open from disk again, replaying the write-ahead log
+    drop(store);
+    let store = BTreeKVEngine::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+#[test]
+fn btree_remove_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = BTreeKVEngine::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert!(store.remove("key1".to_owned()).is_ok());
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(store.remove("key1".to_owned()).is_err());
+
+    Ok(())
+}
+
+// Insert enough keys to split nodes over several levels and to spill large
+// values over overflow pages, across checkpoints.
+#[test]
+fn btree_splits_and_overflow() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = BTreeKVEngine::open(temp_dir.path())?;
+    let large = "x".repeat(10_000);
+
+    for key_id in 0..5000 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+    }
+    store.set("large".to_owned(), large.to_owned())?;
+    for key_id in (0..5000).step_by(2) {
+        store.remove(format!("key{}", key_id))?;
+    }
+
+    let check = |store: &BTreeKVEngine| -> Result<()> {
+        for key_id in 0..5000 {
+            let expected = if key_id % 2 == 0 {
+                None
+            } else {
+                Some(format!("value{}", key_id))
+            };
+            assert_eq!(store.get(format!("key{}", key_id))?, expected);
+        }
+        assert_eq!(store.get("large".to_owned())?, Some(large.to_owned()));
+        Ok(())
+    };
+    check(&store)?;
+
+    drop(store);
+    let store = BTreeKVEngine::open(temp_dir.path())?;
+    check(&store)?;
+
+    Ok(())
+}
+
+// Should page through keys spread over many leaves
+#[test]
+fn btree_scan_from() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = BTreeKVEngine::open(temp_dir.path())?;
+    for key_id in 0..1000 {
+        store.set(format!("key{:04}", key_id), key_id.to_string())?;
+    }
+    store.set("aaa".to_owned(), "value".to_owned())?;
+    store.set("zzz".to_owned(), "value".to_owned())?;
+
+    let mut keys = Vec::new();
+    let mut after = None;
+    loop {
+        let page = store.scan_from("key", after.as_deref(), 64)?;
+        if page.is_empty() {
+            break;
+        }
+        assert!(page.len() <= 64);
+        after = page.last().map(|pair| pair.key.to_owned());
+        keys.extend(page.into_iter().map(|pair| pair.key));
+    }
+    let expected: Vec<_> = (0..1000).map(|key_id| format!("key{:04}", key_id)).collect();
+    assert_eq!(keys, expected);
+
+    Ok(())
+}
+
+#[test]
+fn btree_wrong_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(BTreeKVEngine::open(temp_dir.path())?);
+    assert!(KVStore::open(temp_dir.path()).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use kvs::{KVCache, KVEngine, Result, SledKVEngine};
+use tempfile::TempDir;
+
+// Should serve repeated reads from the cache
+#[test]
+fn cache_hit_and_miss() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KVCache::new(SledKVEngine::open(temp_dir.path())?, 16);
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    let stats = store.cache_stats();
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 2);
+    assert_eq!(stats.entries, 1);
+
+    Ok(())
+}
+
+// Should never return a stale value after set or remove
+#[test]
+fn cache_invalidation() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KVCache::new(SledKVEngine::open(temp_dir.path())?, 16);
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should evict the least recently used value once full
+#[test]
+fn cache_eviction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KVCache::new(SledKVEngine::open(temp_dir.path())?, 2);
+
+    for i in 0..3 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+        store.get(format!("key{}", i))?;
+    }
+    assert_eq!(store.cache_stats().entries, 2);
+
+    // key0 was evicted, key2 is still cached
+    store.get("key2".to_owned())?;
+    store.get("key0".to_owned())?;
+    let stats = store.cache_stats();
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 4);
+
+    Ok(())
+}
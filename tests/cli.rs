@@ -1,6 +1,9 @@
 use assert_cmd::prelude::*;
+use kvs::{KVEngine, SledKVEngine};
 use predicates::str::{contains, is_empty};
 use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::TcpStream;
 use std::process::Command;
 use std::sync::mpsc;
 use std::thread;
@@ -212,6 +215,226 @@ fn cli_wrong_engine() {
     }
 }
 
+#[test]
+fn cli_detect_engine() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--engine", "sled", "--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    child.kill().expect("server exited before killed");
+
+    // without --engine, the sled directory is reopened with sled
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    assert!(child.try_wait().unwrap().is_none(), "server exited");
+    child.kill().expect("server exited before killed");
+
+    // an explicit conflicting engine is still refused
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    cmd.args(&["--engine", "kvs", "--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn cli_data_dir_lock() {
+    let temp_dir = TempDir::new().unwrap();
+    let data_dir = temp_dir.path().join("data");
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--engine", "sled", "--addr", "127.0.0.1:4007", "--data-dir"])
+        .arg(&data_dir)
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    assert!(data_dir.join("my_old_db").exists());
+
+    // a second server cannot open the same directory
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    cmd.args(&["--engine", "sled", "--addr", "127.0.0.1:4008", "--data-dir"])
+        .arg(&data_dir)
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    child.kill().expect("server exited before killed");
+}
+
+#[test]
+fn cli_config_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("kvs.toml");
+
+    // a bad value is reported before the server starts
+    fs::write(&config_path, "addr = \"127.0.0.1:4010\"\nthreads = 0\n").unwrap();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--config"])
+        .arg(&config_path)
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("threads"));
+
+    fs::write(&config_path, "addr = \"127.0.0.1:4011\"\nengine = \"sled\"\n").unwrap();
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--config"])
+        .arg(&config_path)
+        .args(&["--addr", "127.0.0.1:4010"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    // the --addr flag wins over the file
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", "127.0.0.1:4010"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    child.kill().expect("server exited before killed");
+    assert!(temp_dir.path().join("my_old_db").exists());
+}
+
+#[test]
+fn cli_get_strict() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--engine", "sled", "--addr", "127.0.0.1:4015"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "Key not found", "--addr", "127.0.0.1:4015"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    // a stored value is never mistaken for a missing key
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--strict", "--addr", "127.0.0.1:4015"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("Key not found\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key2", "--strict", "--addr", "127.0.0.1:4015"])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(2)
+        .stdout("Key not found\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key2", "--addr", "127.0.0.1:4015"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    child.kill().expect("server exited before killed");
+}
+
+#[test]
+fn cli_read_only() {
+    let temp_dir = TempDir::new().unwrap();
+    let store = SledKVEngine::open(temp_dir.path()).unwrap();
+    store.set("key1".to_owned(), "value1".to_owned()).unwrap();
+    store.set("key2".to_owned(), "value2".to_owned()).unwrap();
+    drop(store);
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--read-only", "--addr", "127.0.0.1:4009"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", "127.0.0.1:4009"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["scan", "key", "--addr", "127.0.0.1:4009"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("key1 value1\nkey2 value2\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value3", "--addr", "127.0.0.1:4009"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["rm", "key2", "--addr", "127.0.0.1:4009"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    child.kill().expect("server exited before killed");
+}
+
+#[cfg(unix)]
+#[test]
+fn cli_graceful_shutdown() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--engine", "memory", "--dump-interval", "3600"])
+        .args(&["--addr", "127.0.0.1:4014"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", "127.0.0.1:4014"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::new("kill")
+        .args(&["-TERM", &child.id().to_string()])
+        .assert()
+        .success();
+    let status = child.wait().unwrap();
+    assert!(status.success());
+
+    // the store was dumped on the way out
+    let dump = fs::read_to_string(temp_dir.path().join("memory_dump.json")).unwrap();
+    assert!(dump.contains("value1"));
+}
+
 fn cli_access_server(engine: &str, addr: &str) {
     let (sender, receiver) = mpsc::sync_channel(0);
     let temp_dir = TempDir::new().unwrap();
@@ -335,3 +558,145 @@ fn cli_access_server_kvs_engine() {
 fn cli_access_server_sled_engine() {
     cli_access_server("sled", "127.0.0.1:4005");
 }
+
+#[test]
+fn cli_resp_protocol() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--protocol", "http", "--addr", "127.0.0.1:4016"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("unknown protocol http"));
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--protocol", "resp", "--engine", "memory"])
+        .args(&["--addr", "127.0.0.1:4016"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let mut stream = TcpStream::connect("127.0.0.1:4016").unwrap();
+    stream.write_all(b"SET key1 value1\r\nGET key1\r\nQUIT\r\n").unwrap();
+    let mut replies = String::new();
+    stream.read_to_string(&mut replies).unwrap();
+    assert_eq!(replies, "+OK\r\n$6\r\nvalue1\r\n+OK\r\n");
+
+    child.kill().expect("server exited before killed");
+}
+
+// Should serve clients over a Unix domain socket, removed on shutdown
+#[cfg(unix)]
+#[test]
+fn cli_unix_socket() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let socket = temp_dir.path().join("kvs.sock");
+    let addr = format!("unix:{}", socket.display());
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--engine", "memory", "--addr", &addr, "--socket-mode", "600"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    let mode = fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", &addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", &addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    Command::new("kill")
+        .args(&["-TERM", &child.id().to_string()])
+        .assert()
+        .success();
+    assert!(child.wait().unwrap().success());
+    assert!(!socket.exists());
+}
+
+// Should explain why an address is rejected
+#[test]
+fn cli_invalid_addr() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key", "--addr", "127.0.0.1:port"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("invalid port port"));
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--engine", "memory", "--addr", "[::1:4000"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("missing ]"));
+}
+
+// Should encrypt traffic when given certificates
+#[test]
+fn cli_tls() {
+    let temp_dir = TempDir::new().unwrap();
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let cert = temp_dir.path().join("cert.pem");
+    let key = temp_dir.path().join("key.pem");
+    fs::write(&cert, generated.serialize_pem().unwrap()).unwrap();
+    fs::write(&key, generated.serialize_private_key_pem()).unwrap();
+    let cert = cert.to_str().unwrap();
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--engine", "memory", "--addr", "127.0.0.1:4017"])
+        .args(&["--tls-cert", cert, "--tls-key", key.to_str().unwrap()])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", "localhost:4017", "--tls-ca", cert])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", "127.0.0.1:4017", "--insecure"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", "127.0.0.1:4017"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+    // a client certificate is never sent over plain TCP
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", "localhost:4017"])
+        .args(&["--tls-cert", cert, "--tls-key", key.to_str().unwrap()])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("--tls-ca"));
+
+    child.kill().expect("server exited before killed");
+}
@@ -0,0 +1,122 @@
+// Servers started on ephemeral ports for the integration tests, each test
+// crate using only some of them.
+#![allow(dead_code)]
+
+use kvs::{KVClient, KVEngine, KVRequest, KVServer, Result, ServerHandle, ShutdownHandle};
+use slog::{Discard, Logger};
+use std::net::SocketAddr;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[cfg(feature = "async")]
+use kvs::AsyncKVServer;
+#[cfg(feature = "grpc")]
+use kvs::grpc::kv_service_client::KvServiceClient;
+#[cfg(feature = "async")]
+use tokio::sync::oneshot;
+#[cfg(feature = "grpc")]
+use tonic::transport::Channel;
+
+pub fn logger() -> Logger {
+    Logger::root(Discard, o!())
+}
+
+/// Serves `server` from a thread on an ephemeral port of the loopback.
+pub fn spawn<E: KVEngine + Send + 'static>(server: KVServer<E>) -> Result<ServerHandle> {
+    server.spawn("127.0.0.1:0".to_owned(), &logger())
+}
+
+/// Waits for a server started on another thread to listen on `addr`.
+pub fn wait_for(addr: &str) {
+    while KVClient::new(addr.to_owned(), KVRequest::Stats).is_err() {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+pub struct Gateway {
+    pub addr: SocketAddr,
+    shutdown: ShutdownHandle,
+    thread: JoinHandle<Result<()>>,
+}
+
+impl Gateway {
+    pub fn stop(self) -> Result<()> {
+        self.shutdown.shutdown();
+        self.thread.join().unwrap()
+    }
+}
+
+/// Serves the HTTP gateway of `server` from a thread on an ephemeral port.
+pub fn start_http<E: KVEngine + Send + 'static>(server: KVServer<E>) -> Result<Gateway> {
+    let gateway = server.http_gateway("127.0.0.1:0")?;
+    let addr = gateway.local_addr();
+    let thread = thread::spawn(move || gateway.run(&logger()));
+    Ok(Gateway {
+        addr,
+        shutdown: server.shutdown_handle(),
+        thread,
+    })
+}
+
+#[cfg(feature = "grpc")]
+pub struct GrpcServer {
+    pub client: KvServiceClient<Channel>,
+    pub shutdown: ShutdownHandle,
+    pub task: tokio::task::JoinHandle<Result<()>>,
+}
+
+#[cfg(feature = "grpc")]
+impl GrpcServer {
+    pub async fn stop(self) -> Result<()> {
+        self.shutdown.shutdown();
+        self.task.await.unwrap()
+    }
+}
+
+/// Serves `server` over gRPC on an ephemeral port, along with a connected
+/// client.
+#[cfg(feature = "grpc")]
+pub async fn start_grpc<E: KVEngine + Send + 'static>(server: KVServer<E>) -> Result<GrpcServer> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let shutdown = server.shutdown_handle();
+    let task = tokio::spawn(async move { server.run_grpc(listener, &logger()).await });
+    let client = KvServiceClient::connect(format!("http://{}", addr)).await.unwrap();
+    Ok(GrpcServer {
+        client,
+        shutdown,
+        task,
+    })
+}
+
+#[cfg(feature = "async")]
+pub struct AsyncServer {
+    pub addr: String,
+    stop: oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<Result<()>>,
+}
+
+#[cfg(feature = "async")]
+impl AsyncServer {
+    pub async fn stop(self) -> Result<()> {
+        let _ = self.stop.send(());
+        self.task.await.unwrap()
+    }
+}
+
+/// Serves `server` on an ephemeral port until stopped.
+#[cfg(feature = "async")]
+pub async fn start_async<E: KVEngine + Send + 'static>(
+    server: AsyncKVServer<E>,
+) -> Result<AsyncServer> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?.to_string();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        server.serve(listener, shutdown, &logger()).await
+    });
+    Ok(AsyncServer { addr, stop, task })
+}
@@ -0,0 +1,190 @@
+use kvs::{
+    Durability, EngineOptions, KVEngine, LsmKVEngine, Result, ServerConfig, ServerProtocol,
+};
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(name, val)| (name.to_string(), val.to_string()))
+        .collect()
+}
+
+// Should read every setting from a TOML file, keeping defaults for the others
+#[test]
+fn config_from_file() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("kvs.toml");
+    fs::write(
+        &path,
+        r#"
+addr = "127.0.0.1:5000"
+engine = "lsm"
+data_dir = "/var/lib/kvs"
+durability = "sync"
+
+[compaction]
+l0_trigger = 8
+"#,
+    )?;
+
+    let config = ServerConfig::from_file(&path)?;
+    config.validate()?;
+    assert_eq!(config.addr, "127.0.0.1:5000");
+    assert_eq!(config.engine, Some("lsm".to_owned()));
+    assert_eq!(config.data_dir, Some(PathBuf::from("/var/lib/kvs")));
+    assert_eq!(config.durability, Durability::Sync);
+    assert_eq!(config.compaction.l0_trigger, 8);
+    assert_eq!(config.threads, 1);
+    assert_eq!(config.log_level, "info");
+
+    let options = config.engine_options();
+    assert!(options.sync);
+    assert_eq!(options.l0_compaction_trigger, 8);
+    assert_eq!(options.memtable_limit, EngineOptions::default().memtable_limit);
+
+    Ok(())
+}
+
+// Should refuse unknown keys and badly typed values
+#[test]
+fn config_invalid_file() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("kvs.toml");
+
+    fs::write(&path, "adress = \"127.0.0.1:5000\"\n")?;
+    assert!(ServerConfig::from_file(&path).is_err());
+
+    fs::write(&path, "threads = \"four\"\n")?;
+    assert!(ServerConfig::from_file(&path).is_err());
+
+    fs::write(&path, "durability = \"never\"\n")?;
+    assert!(ServerConfig::from_file(&path).is_err());
+
+    assert!(ServerConfig::from_file(&temp_dir.path().join("missing.toml")).is_err());
+
+    Ok(())
+}
+
+// Should override settings with KVS_* variables only
+#[test]
+fn config_env_override() -> Result<()> {
+    let mut config = ServerConfig::default();
+    config.apply_env(vars(&[
+        ("KVS_ADDR", "127.0.0.1:5001"),
+        ("KVS_PROTOCOL", "resp"),
+        ("KVS_SOCKET_MODE", "660"),
+        ("KVS_THREADS", "4"),
+        ("KVS_DURABILITY", "sync"),
+        ("KVS_MEMTABLE_LIMIT", "1024"),
+        ("KVS_LOG_LEVEL", "debug"),
+        ("PATH", "/usr/bin"),
+    ]))?;
+    config.validate()?;
+    assert_eq!(config.addr, "127.0.0.1:5001");
+    assert_eq!(config.protocol, ServerProtocol::Resp);
+    assert_eq!(config.socket_mode, Some(0o660));
+    assert_eq!(config.threads, 4);
+    assert_eq!(config.durability, Durability::Sync);
+    assert_eq!(config.compaction.memtable_limit, 1024);
+    assert_eq!(config.log_level, "debug");
+
+    assert!(config.apply_env(vars(&[("KVS_THREADS", "many")])).is_err());
+    assert!(config.apply_env(vars(&[("KVS_DURABILITY", "never")])).is_err());
+    assert!(config.apply_env(vars(&[("KVS_PROTOCOL", "http")])).is_err());
+    assert!(config.apply_env(vars(&[("KVS_SOCKET_MODE", "rw-rw----")])).is_err());
+
+    Ok(())
+}
+
+// Should report every invalid setting
+#[test]
+fn config_validate() {
+    let valid = ServerConfig::default();
+    assert!(valid.validate().is_ok());
+
+    let mut config = valid.clone();
+    config.addr = "127.0.0.1:port".to_owned();
+    assert!(config.validate().is_err());
+
+    let mut config = valid.clone();
+    config.addr = "unix:".to_owned();
+    assert!(config.validate().is_err());
+
+    let mut config = valid.clone();
+    config.socket_mode = Some(0o1777);
+    assert!(config.validate().is_err());
+
+    let mut config = valid.clone();
+    config.http_addr = Some("localhost:http".to_owned());
+    assert!(config.validate().is_err());
+
+    let mut config = valid.clone();
+    config.grpc_addr = Some("localhost:grpc".to_owned());
+    assert!(config.validate().is_err());
+
+    let mut config = valid.clone();
+    config.tls_cert = Some(PathBuf::from("cert.pem"));
+    assert!(config.validate().is_err());
+
+    let mut config = valid.clone();
+    config.tls_client_ca = Some(PathBuf::from("ca.pem"));
+    assert!(config.validate().is_err());
+
+    // plain text listeners would bypass TLS
+    let mut tls = valid.clone();
+    tls.tls_cert = Some(PathBuf::from("cert.pem"));
+    tls.tls_key = Some(PathBuf::from("key.pem"));
+    assert!(tls.validate().is_ok());
+    let mut config = tls.clone();
+    config.http_addr = Some("127.0.0.1:8080".to_owned());
+    assert!(config.validate().is_err());
+    let mut config = tls;
+    config.grpc_addr = Some("127.0.0.1:50051".to_owned());
+    assert!(config.validate().is_err());
+
+    let mut config = valid.clone();
+    config.engine = Some("rocksdb".to_owned());
+    assert!(config.validate().is_err());
+
+    let mut config = valid.clone();
+    config.engine = Some("memory".to_owned());
+    config.durability = Durability::Sync;
+    assert!(config.validate().is_err());
+
+    let mut config = valid.clone();
+    config.threads = 0;
+    assert!(config.validate().is_err());
+
+    let mut config = valid.clone();
+    config.compaction.l0_trigger = 1;
+    assert!(config.validate().is_err());
+
+    let mut config = valid;
+    config.log_level = "verbose".to_owned();
+    assert!(config.validate().is_err());
+}
+
+// Should flush the lsm memtable at the configured size
+#[test]
+fn config_lsm_thresholds() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = EngineOptions {
+        memtable_limit: 64,
+        l0_compaction_trigger: 100,
+        ..EngineOptions::default()
+    };
+    let store = LsmKVEngine::open_with(temp_dir.path(), &options)?;
+    for i in 0..20 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let l0_tables = store.stats()["l0_tables"];
+    assert!(l0_tables > 1, "memtable never flushed");
+    for i in 0..20 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,200 @@
+#![cfg(feature = "grpc")]
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+use common::{logger, start_grpc};
+use kvs::grpc::{
+    operation, operation_result, watch_event, BatchRequest, DeleteRequest, GetRequest,
+    GetResponse, KeyValue, Operation, ScanRequest, SetRequest, WatchRequest,
+};
+use kvs::{KVEngine, KVServer, MemoryKVEngine, Result};
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use tonic::Code;
+
+fn set(key: &str, value: &str) -> SetRequest {
+    SetRequest {
+        key: key.to_owned(),
+        value: value.to_owned(),
+    }
+}
+
+fn get(key: &str) -> GetRequest {
+    GetRequest {
+        key: key.to_owned(),
+    }
+}
+
+fn delete(key: &str) -> DeleteRequest {
+    DeleteRequest {
+        key: key.to_owned(),
+    }
+}
+
+// Should get, set and delete keys of the engine
+#[tokio::test(threaded_scheduler)]
+async fn grpc_keys() -> Result<()> {
+    let engine = MemoryKVEngine::new();
+    let mut server = start_grpc(KVServer::new(engine.clone())).await?;
+
+    server.client.set(set("key1", "value1")).await.unwrap();
+    let resp = server.client.get(get("key1")).await.unwrap().into_inner();
+    let expected = GetResponse {
+        found: true,
+        value: "value1".to_owned(),
+    };
+    assert_eq!(resp, expected);
+    assert_eq!(engine.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    server.client.delete(delete("key1")).await.unwrap();
+    let resp = server.client.get(get("key1")).await.unwrap().into_inner();
+    assert!(!resp.found);
+    let status = server.client.delete(delete("key1")).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    server.stop().await
+}
+
+// Should stream the pairs under a prefix in key order
+#[tokio::test(threaded_scheduler)]
+async fn grpc_scan() -> Result<()> {
+    let mut server = start_grpc(KVServer::new(MemoryKVEngine::new())).await?;
+    for key in &["user:2", "other", "user:1"] {
+        server.client.set(set(key, key)).await.unwrap();
+    }
+
+    let request = ScanRequest {
+        prefix: "user:".to_owned(),
+    };
+    let mut stream = server.client.scan(request).await.unwrap().into_inner();
+    let mut pairs = Vec::new();
+    while let Some(pair) = stream.message().await.unwrap() {
+        pairs.push(pair);
+    }
+    let expected: Vec<_> = ["user:1", "user:2"]
+        .iter()
+        .map(|key| KeyValue {
+            key: key.to_string(),
+            value: key.to_string(),
+        })
+        .collect();
+    assert_eq!(pairs, expected);
+
+    server.stop().await
+}
+
+// Should stream scans larger than the batches read from the engine
+#[tokio::test(threaded_scheduler)]
+async fn grpc_scan_batches() -> Result<()> {
+    let engine = MemoryKVEngine::new();
+    for i in 0..2500 {
+        engine.set(format!("key{:04}", i), i.to_string())?;
+    }
+    let mut server = start_grpc(KVServer::new(engine)).await?;
+
+    let request = ScanRequest {
+        prefix: "key".to_owned(),
+    };
+    let mut stream = server.client.scan(request).await.unwrap().into_inner();
+    let mut keys = Vec::new();
+    while let Some(pair) = stream.message().await.unwrap() {
+        keys.push(pair.key);
+    }
+    let expected: Vec<_> = (0..2500).map(|i| format!("key{:04}", i)).collect();
+    assert_eq!(keys, expected);
+
+    server.stop().await
+}
+
+// Should stream later writes under a prefix, whatever protocol made them
+#[tokio::test(threaded_scheduler)]
+async fn grpc_watch() -> Result<()> {
+    let server = KVServer::new(MemoryKVEngine::new());
+    let http = server.http_gateway("127.0.0.1:0")?;
+    let mut grpc = start_grpc(server).await?;
+
+    let request = WatchRequest {
+        prefix: "user:".to_owned(),
+    };
+    let mut stream = grpc.client.watch(request).await.unwrap().into_inner();
+    grpc.client.set(set("other", "value1")).await.unwrap();
+    grpc.client.set(set("user:1", "value1")).await.unwrap();
+    grpc.client.delete(delete("user:1")).await.unwrap();
+
+    let event = stream.message().await.unwrap().unwrap();
+    assert_eq!(event.kind, watch_event::Kind::Set as i32);
+    assert_eq!((event.key.as_str(), event.value.as_str()), ("user:1", "value1"));
+    let event = stream.message().await.unwrap().unwrap();
+    assert_eq!(event.kind, watch_event::Kind::Delete as i32);
+    assert_eq!(event.key, "user:1");
+
+    // writes through the HTTP gateway are seen as well
+    let addr = http.local_addr();
+    let gateway = thread::spawn(move || http.run(&logger()));
+    let mut conn = TcpStream::connect(addr)?;
+    write!(
+        conn,
+        "PUT /keys/user:2 HTTP/1.1\r\nHost: {}\r\nContent-Length: 6\r\n\r\nvalue2",
+        addr
+    )?;
+    let event = stream.message().await.unwrap().unwrap();
+    assert_eq!((event.key.as_str(), event.value.as_str()), ("user:2", "value2"));
+
+    // the stream ends with the server
+    grpc.shutdown.shutdown();
+    assert!(stream.message().await.unwrap().is_none());
+    gateway.join().unwrap()?;
+    grpc.task.await.unwrap()
+}
+
+// Should run every operation of a batch, reporting failures one by one
+#[tokio::test(threaded_scheduler)]
+async fn grpc_batch() -> Result<()> {
+    let mut server = start_grpc(KVServer::new(MemoryKVEngine::new())).await?;
+
+    let operations = vec![
+        operation::Op::Set(set("key1", "value1")),
+        operation::Op::Get(get("key1")),
+        operation::Op::Delete(delete("key2")),
+        operation::Op::Delete(delete("key1")),
+    ];
+    let request = BatchRequest {
+        operations: operations.into_iter().map(|op| Operation { op: Some(op) }).collect(),
+    };
+    let results = server.client.batch(request).await.unwrap().into_inner().results;
+    let outcomes: Vec<_> = results.into_iter().map(|result| result.outcome.unwrap()).collect();
+
+    assert!(matches!(outcomes[0], operation_result::Outcome::Set(_)));
+    match &outcomes[1] {
+        operation_result::Outcome::Get(resp) => assert_eq!(resp.value, "value1"),
+        other => panic!("unexpected outcome {:?}", other),
+    }
+    match &outcomes[2] {
+        operation_result::Outcome::Error(error) => assert_eq!(error.code, "NotFound"),
+        other => panic!("unexpected outcome {:?}", other),
+    }
+    assert!(matches!(outcomes[3], operation_result::Outcome::Delete(_)));
+
+    server.stop().await
+}
+
+// Should refuse writes on a read-only server
+#[tokio::test(threaded_scheduler)]
+async fn grpc_read_only() -> Result<()> {
+    let engine = MemoryKVEngine::new();
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    let mut server = start_grpc(KVServer::read_only(engine)).await?;
+
+    let resp = server.client.get(get("key1")).await.unwrap().into_inner();
+    assert_eq!(resp.value, "value1");
+    let status = server.client.set(set("key1", "value2")).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    let status = server.client.delete(delete("key1")).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    server.stop().await
+}
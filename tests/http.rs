@@ -0,0 +1,273 @@
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+use common::{start_http, Gateway};
+use kvs::{KVEngine, KVServer, MemoryKVEngine, Result};
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, val)| val.as_str())
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8(self.body.clone()).unwrap()
+    }
+
+    fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+// Sends one request, closing the connection after the response.
+fn request(
+    gateway: &Gateway,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<HttpResponse> {
+    let mut stream = TcpStream::connect(gateway.addr)?;
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        gateway.addr,
+        body.len()
+    );
+    for (name, val) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, val));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw)?;
+    let split = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8(raw[..split].to_vec())?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+    let headers = lines
+        .filter_map(|line| {
+            let i = line.find(':')?;
+            Some((line[..i].to_owned(), line[i + 1..].trim().to_owned()))
+        })
+        .collect();
+    Ok(HttpResponse {
+        status,
+        headers,
+        body: raw[split + 4..].to_vec(),
+    })
+}
+
+// Should map GET, PUT and DELETE onto the engine
+#[test]
+fn http_keys() -> Result<()> {
+    let gateway = start_http(KVServer::new(MemoryKVEngine::new()))?;
+
+    let resp = request(&gateway, "PUT", "/keys/key1", &[], b"value1")?;
+    assert_eq!(resp.status, 204);
+    let resp = request(&gateway, "GET", "/keys/key1", &[], b"")?;
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.text(), "value1");
+    assert!(resp.header("Content-Type").unwrap().starts_with("text/plain"));
+    assert!(resp.header("ETag").is_some());
+
+    let accept = [("Accept", "application/json")];
+    let resp = request(&gateway, "GET", "/keys/key1", &accept, b"")?;
+    assert_eq!(resp.json(), json!({"key": "key1", "val": "value1"}));
+
+    // keys are percent-decoded
+    request(&gateway, "PUT", "/keys/a%20key%2F1", &[], b"value2")?;
+    let resp = request(&gateway, "GET", "/keys/a%20key%2F1", &[], b"")?;
+    assert_eq!(resp.text(), "value2");
+
+    let resp = request(&gateway, "DELETE", "/keys/key1", &[], b"")?;
+    assert_eq!(resp.status, 204);
+    let resp = request(&gateway, "GET", "/keys/key1", &[], b"")?;
+    assert_eq!(resp.status, 404);
+    assert_eq!(resp.json()["code"], "NotFound");
+    let resp = request(&gateway, "DELETE", "/keys/key1", &[], b"")?;
+    assert_eq!(resp.status, 404);
+
+    assert_eq!(request(&gateway, "GET", "/unknown", &[], b"")?.status, 404);
+    assert_eq!(request(&gateway, "POST", "/keys/key1", &[], b"")?.status, 405);
+
+    gateway.stop()
+}
+
+// Should answer 412 when a conditional write does not hold
+#[test]
+fn http_conditional_writes() -> Result<()> {
+    let gateway = start_http(KVServer::new(MemoryKVEngine::new()))?;
+    let create = [("If-None-Match", "*")];
+
+    let resp = request(&gateway, "PUT", "/keys/key1", &create, b"value1")?;
+    assert_eq!(resp.status, 204);
+    let etag = resp.header("ETag").unwrap().to_owned();
+    let resp = request(&gateway, "PUT", "/keys/key1", &create, b"value2")?;
+    assert_eq!(resp.status, 412);
+
+    let stale = [("If-Match", "\"0000000000000000\"")];
+    let resp = request(&gateway, "PUT", "/keys/key1", &stale, b"value2")?;
+    assert_eq!(resp.status, 412);
+    let resp = request(&gateway, "DELETE", "/keys/key1", &stale, b"")?;
+    assert_eq!(resp.status, 412);
+
+    let current = [("If-Match", etag.as_str())];
+    let resp = request(&gateway, "PUT", "/keys/key1", &current, b"value2")?;
+    assert_eq!(resp.status, 204);
+    let resp = request(&gateway, "GET", "/keys/key1", &[], b"")?;
+    assert_eq!(resp.text(), "value2");
+
+    // the etag changed along with the value
+    let resp = request(&gateway, "DELETE", "/keys/key1", &current, b"")?;
+    assert_eq!(resp.status, 412);
+
+    gateway.stop()
+}
+
+// Should store binary bodies and give them back on request
+#[test]
+fn http_content_types() -> Result<()> {
+    let gateway = start_http(KVServer::new(MemoryKVEngine::new()))?;
+    let binary = [("Content-Type", "application/octet-stream")];
+    let bytes = [0u8, 159, 146, 150, 255];
+
+    let resp = request(&gateway, "PUT", "/keys/bin", &binary, &bytes)?;
+    assert_eq!(resp.status, 204);
+    let accept = [("Accept", "application/octet-stream")];
+    let resp = request(&gateway, "GET", "/keys/bin", &accept, b"")?;
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Content-Type"), Some("application/octet-stream"));
+    assert_eq!(resp.body, bytes);
+    let resp = request(&gateway, "GET", "/keys/bin", &[], b"")?;
+    assert_eq!(resp.header("Content-Type"), Some("application/octet-stream"));
+    assert_eq!(resp.body, bytes);
+    let resp = request(&gateway, "GET", "/keys/bin", &[("Accept", "application/json")], b"")?;
+    assert_eq!(resp.status, 406);
+
+    // text values are not mistaken for base64
+    request(&gateway, "PUT", "/keys/text", &[], b"test")?;
+    let resp = request(&gateway, "GET", "/keys/text", &accept, b"")?;
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.body, b"test");
+    let resp = request(&gateway, "PUT", "/keys/text", &[], b"\0base64:AAE=")?;
+    assert_eq!(resp.status, 400);
+
+    // raw bytes are only accepted as such
+    let resp = request(&gateway, "PUT", "/keys/bin", &[], &bytes)?;
+    assert_eq!(resp.status, 400);
+
+    let json = [("Content-Type", "application/json")];
+    request(&gateway, "PUT", "/keys/key1", &json, b"\"value \\\"1\\\"\"")?;
+    let resp = request(&gateway, "GET", "/keys/key1", &[], b"")?;
+    assert_eq!(resp.text(), "value \"1\"");
+    let resp = request(&gateway, "PUT", "/keys/key1", &json, b"{}")?;
+    assert_eq!(resp.status, 400);
+
+    gateway.stop()
+}
+
+// Should list keys by prefix and run batches
+#[test]
+fn http_scan_and_batch() -> Result<()> {
+    let gateway = start_http(KVServer::new(MemoryKVEngine::new()))?;
+    for key in &["user:1", "user:2", "other"] {
+        request(&gateway, "PUT", &format!("/keys/{}", key), &[], key.as_bytes())?;
+    }
+
+    let resp = request(&gateway, "GET", "/keys?prefix=user%3A", &[], b"")?;
+    assert_eq!(resp.status, 200);
+    let expected = json!([
+        {"key": "user:1", "val": "user:1"},
+        {"key": "user:2", "val": "user:2"},
+    ]);
+    assert_eq!(resp.json(), expected);
+    let resp = request(&gateway, "GET", "/keys", &[], b"")?;
+    assert_eq!(resp.json().as_array().unwrap().len(), 3);
+
+    let batch = json!([
+        {"op": "set", "key": "key1", "value": "value1"},
+        {"op": "get", "key": "key1"},
+        {"op": "delete", "key": "other"},
+        {"op": "get", "key": "other"},
+    ]);
+    let resp = request(&gateway, "POST", "/batch", &[], batch.to_string().as_bytes())?;
+    assert_eq!(resp.status, 200);
+    let results = resp.json();
+    assert_eq!(results[0], json!({"status": 204}));
+    assert_eq!(results[1], json!({"status": 200, "value": "value1"}));
+    assert_eq!(results[2], json!({"status": 204}));
+    assert_eq!(results[3]["status"], 404);
+
+    let resp = request(&gateway, "POST", "/batch", &[], b"[{\"op\": \"incr\"}]")?;
+    assert_eq!(resp.status, 400);
+
+    // text values are not mistaken for base64 either
+    let batch = json!([
+        {"op": "set", "key": "key2", "value": "\u{0}base64:AAE="},
+        {"op": "get", "key": "key2"},
+    ]);
+    let resp = request(&gateway, "POST", "/batch", &[], batch.to_string().as_bytes())?;
+    let results = resp.json();
+    assert_eq!(results[0]["status"], 400);
+    assert_eq!(results[1]["status"], 404);
+
+    gateway.stop()
+}
+
+// Should refuse bodies over the limit without reading them
+#[test]
+fn http_body_too_large() -> Result<()> {
+    let gateway = start_http(KVServer::new(MemoryKVEngine::new()))?;
+
+    let mut stream = TcpStream::connect(gateway.addr)?;
+    let head = format!(
+        "PUT /keys/key1 HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+        gateway.addr,
+        64 * 1024 * 1024 + 1
+    );
+    stream.write_all(head.as_bytes())?;
+    // the gateway skips whatever body was sent before answering
+    stream.shutdown(Shutdown::Write)?;
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw)?;
+    assert!(raw.starts_with("HTTP/1.1 413"), "{}", raw);
+
+    let resp = request(&gateway, "GET", "/keys/key1", &[], b"")?;
+    assert_eq!(resp.status, 404);
+
+    gateway.stop()
+}
+
+// Should refuse writes through a read-only server
+#[test]
+fn http_read_only() -> Result<()> {
+    let engine = MemoryKVEngine::new();
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    let gateway = start_http(KVServer::read_only(engine))?;
+
+    let resp = request(&gateway, "GET", "/keys/key1", &[], b"")?;
+    assert_eq!(resp.text(), "value1");
+    let resp = request(&gateway, "PUT", "/keys/key1", &[], b"value2")?;
+    assert_eq!(resp.status, 403);
+    let resp = request(&gateway, "DELETE", "/keys/key1", &[], b"")?;
+    assert_eq!(resp.status, 403);
+
+    gateway.stop()
+}
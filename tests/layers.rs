@@ -0,0 +1,57 @@
+use kvs::{build_stack, KVEngine, KVError, LayerConfig, Result, SledKVEngine};
+use tempfile::TempDir;
+
+// Should parse layer specifications given on the command line
+#[test]
+fn parse_layer_config() {
+    assert_eq!("cache=10".parse::<LayerConfig>().unwrap(), LayerConfig::Cache(10));
+    assert_eq!("metrics".parse::<LayerConfig>().unwrap(), LayerConfig::Metrics);
+    assert_eq!(
+        "prefix=app:".parse::<LayerConfig>().unwrap(),
+        LayerConfig::Prefix("app:".to_owned())
+    );
+    assert_eq!(
+        "validate=4,8".parse::<LayerConfig>().unwrap(),
+        LayerConfig::Validate {
+            max_key_len: 4,
+            max_val_len: 8
+        }
+    );
+    assert!("cache".parse::<LayerConfig>().is_err());
+    assert!("unknown".parse::<LayerConfig>().is_err());
+}
+
+// Should apply every layer of the stack, outermost first
+#[test]
+fn layered_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = SledKVEngine::open(temp_dir.path())?;
+    let layers = vec![
+        LayerConfig::Metrics,
+        LayerConfig::Validate {
+            max_key_len: 8,
+            max_val_len: 8,
+        },
+        LayerConfig::Cache(16),
+        LayerConfig::Prefix("app:".to_owned()),
+    ];
+    let store = build_stack(engine.clone(), &layers);
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(engine.get("app:key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(engine.get("key1".to_owned())?, None);
+
+    match store.set("key1".to_owned(), "too long value".to_owned()) {
+        Err(KVError::InvalidValue(_)) => {}
+        _ => panic!("value should have been rejected"),
+    }
+
+    let stats = store.stats();
+    assert_eq!(stats["op_sets"], 2);
+    assert_eq!(stats["op_gets"], 1);
+    assert_eq!(stats["op_errors"], 1);
+    assert_eq!(stats["cache_misses"], 1);
+
+    Ok(())
+}
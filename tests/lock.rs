@@ -0,0 +1,49 @@
+use kvs::{KVEngine, KVError, KVStore, Result, SledKVEngine};
+use tempfile::TempDir;
+
+// Should refuse to open a directory already opened for writing
+#[test]
+fn lock_exclusive_open() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KVStore::open(temp_dir.path())?;
+
+    match KVStore::open(temp_dir.path()) {
+        Err(KVError::DirectoryLocked(_)) => {}
+        _ => panic!("directory opened twice"),
+    }
+    match KVStore::open_read_only(temp_dir.path()) {
+        Err(KVError::DirectoryLocked(_)) => {}
+        _ => panic!("directory opened read-only while being written"),
+    }
+    assert!(SledKVEngine::open(temp_dir.path()).is_err());
+
+    // the lock is released with the last clone of the store
+    let clone = store.clone();
+    drop(store);
+    assert!(KVStore::open(temp_dir.path()).is_err());
+    drop(clone);
+    drop(KVStore::open(temp_dir.path())?);
+
+    Ok(())
+}
+
+// Should share the directory between read-only opens only
+#[test]
+fn lock_shared_open() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KVStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    let reader1 = KVStore::open_read_only(temp_dir.path())?;
+    let reader2 = KVStore::open_read_only(temp_dir.path())?;
+    assert!(KVStore::open(temp_dir.path()).is_err());
+
+    match reader1.set("key1".to_owned(), "value2".to_owned()) {
+        Err(KVError::ReadOnly) => {}
+        _ => panic!("read-only store accepted a write"),
+    }
+    assert_eq!(reader2.scan("")?.len(), 1);
+
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use kvs::{EngineOptions, KVEngine, LsmKVEngine, Result, SledKVEngine};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use tempfile::TempDir;
+
+// Should get previously stored value, also after reopening
+#[test]
+fn lsm_get_stored_value() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmKVEngine::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, None);
+
+    // Open from disk again, replaying the write-ahead log
+    drop(store);
+    let store = LsmKVEngine::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+#[test]
+fn lsm_remove_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmKVEngine::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert!(store.remove("key1".to_owned()).is_ok());
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(store.remove("key1".to_owned()).is_err());
+
+    Ok(())
+}
+
+// Write enough data to flush several SSTables and trigger a compaction,
+// overwriting and removing keys along the way.
+#[test]
+fn lsm_flush_and_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmKVEngine::open(temp_dir.path())?;
+    let padding = "x".repeat(1024);
+
+    for iter in 0..6 {
+        for key_id in 0..1000 {
+            let value = format!("{}-{}", iter, padding);
+            store.set(format!("key{}", key_id), value)?;
+        }
+    }
+    for key_id in 0..100 {
+        store.remove(format!("key{}", key_id))?;
+    }
+
+    let stats = store.stats();
+    assert!(stats["l1_tables"] > 0, "no compaction happened: {:?}", stats);
+
+    let check = |store: &LsmKVEngine| -> Result<()> {
+        for key_id in 0..1000 {
+            let expected = if key_id < 100 {
+                None
+            } else {
+                Some(format!("5-{}", padding))
+            };
+            assert_eq!(store.get(format!("key{}", key_id))?, expected);
+        }
+        Ok(())
+    };
+    check(&store)?;
+
+    drop(store);
+    let store = LsmKVEngine::open(temp_dir.path())?;
+    check(&store)?;
+
+    Ok(())
+}
+
+#[test]
+fn lsm_wrong_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(SledKVEngine::open(temp_dir.path())?);
+    assert!(LsmKVEngine::open(temp_dir.path()).is_err());
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(LsmKVEngine::open(temp_dir.path())?);
+    assert!(SledKVEngine::open(temp_dir.path()).is_err());
+
+    Ok(())
+}
+
+// A torn record at the end of the write-ahead log must not hide the writes
+// made after reopening
+#[test]
+fn lsm_torn_wal() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmKVEngine::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    let mut wal = OpenOptions::new()
+        .append(true)
+        .open(temp_dir.path().join("lsm").join("wal.log"))?;
+    wal.write_all(b"{\"key\":\"key2\",\"va")?;
+    drop(wal);
+
+    let store = LsmKVEngine::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    drop(store);
+
+    let store = LsmKVEngine::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+
+    Ok(())
+}
+
+// Pages read from the memtable and tables of every level match a full scan
+#[test]
+fn lsm_scan_from() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = EngineOptions {
+        memtable_limit: 4096,
+        ..EngineOptions::default()
+    };
+    let store = LsmKVEngine::open_with(temp_dir.path(), &options)?;
+    for iter in 0..3 {
+        for key_id in 0..500 {
+            store.set(format!("key{:03}", key_id), format!("{}-{}", iter, key_id))?;
+        }
+    }
+    for key_id in (0..500).step_by(3) {
+        store.remove(format!("key{:03}", key_id))?;
+    }
+    store.set("other".to_owned(), "value".to_owned())?;
+
+    let mut paged = Vec::new();
+    let mut after = None;
+    loop {
+        let page = store.scan_from("key", after.as_deref(), 7)?;
+        if page.is_empty() {
+            break;
+        }
+        after = page.last().map(|pair| pair.key.to_owned());
+        paged.extend(page.into_iter().map(|pair| (pair.key, pair.val)));
+    }
+    let scanned: Vec<_> = store
+        .scan("key")?
+        .into_iter()
+        .map(|pair| (pair.key, pair.val))
+        .collect();
+    assert_eq!(scanned.len(), 333);
+    assert_eq!(paged, scanned);
+
+    Ok(())
+}
+
+// A table whose footer points past its end is reported, not read
+#[test]
+fn lsm_corrupt_sstable_footer() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = EngineOptions {
+        memtable_limit: 1,
+        ..EngineOptions::default()
+    };
+    let store = LsmKVEngine::open_with(temp_dir.path(), &options)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    let table = fs::read_dir(temp_dir.path().join("lsm"))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .find(|path| path.extension().map_or(false, |ext| ext == "sst"))
+        .expect("no sstable written");
+    let mut file = OpenOptions::new().write(true).open(&table)?;
+    file.seek(SeekFrom::End(-8))?;
+    file.write_all(&u64::MAX.to_be_bytes())?;
+    drop(file);
+
+    assert!(LsmKVEngine::open(temp_dir.path()).is_err());
+
+    Ok(())
+}
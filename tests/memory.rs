@@ -0,0 +1,78 @@
+use kvs::{KVEngine, MemoryKVEngine, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// Should get, overwrite and remove values
+#[test]
+fn memory_set_get_remove() -> Result<()> {
+    let store = MemoryKVEngine::new();
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(store.remove("key1".to_owned()).is_err());
+
+    Ok(())
+}
+
+// Should keep every write made from concurrent threads
+#[test]
+fn memory_concurrent_set() -> Result<()> {
+    let store = MemoryKVEngine::new();
+    let barrier = Arc::new(Barrier::new(101));
+    for i in 0..100 {
+        let store = store.clone();
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            store
+                .set(format!("key{}", i), format!("value{}", i))
+                .unwrap();
+            barrier.wait();
+        });
+    }
+    barrier.wait();
+
+    for i in 0..100 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+
+    Ok(())
+}
+
+// Should reload the periodic dump on open
+#[test]
+fn memory_dump_to_disk() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let interval = Duration::from_millis(50);
+    let store = MemoryKVEngine::open_with_dump(temp_dir.path(), interval)?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    // wait for a periodic dump, without relying on its timing
+    let dump_path = temp_dir.path().join("memory_dump.json");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let dumped = fs::read(&dump_path)
+            .ok()
+            .and_then(|dump| serde_json::from_slice::<HashMap<String, String>>(&dump).ok());
+        if dumped.map_or(false, |map| map.contains_key("key1")) {
+            break;
+        }
+        assert!(Instant::now() < deadline, "no dump written");
+        thread::sleep(interval);
+    }
+    drop(store);
+
+    let store = MemoryKVEngine::open_with_dump(temp_dir.path(), interval)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use kvs::{EngineMeta, KVError, LsmKVEngine, Result, SledKVEngine};
+use std::collections::BTreeMap;
+use std::fs;
+use tempfile::TempDir;
+
+// Should record the engine in the metadata file on first open
+#[test]
+fn meta_written_on_first_open() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(SledKVEngine::open(temp_dir.path())?);
+
+    let meta = EngineMeta::read(temp_dir.path())?.expect("metadata file missing");
+    assert_eq!(meta.engine, "sled");
+    assert_eq!(meta.format_version, 1);
+
+    Ok(())
+}
+
+// Should report which engine the directory belongs to
+#[test]
+fn meta_wrong_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(SledKVEngine::open(temp_dir.path())?);
+
+    match LsmKVEngine::open(temp_dir.path()) {
+        Err(KVError::WrongEngine { expected, found }) => {
+            assert_eq!(expected, "lsm");
+            assert_eq!(found, "sled");
+        }
+        _ => panic!("lsm engine opened a sled directory"),
+    }
+
+    Ok(())
+}
+
+// Should refuse data written in a newer format
+#[test]
+fn meta_unsupported_format() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    EngineMeta::new("sled", 99, BTreeMap::new()).write(temp_dir.path())?;
+
+    match SledKVEngine::open(temp_dir.path()) {
+        Err(KVError::UnsupportedFormat { version, .. }) => assert_eq!(version, 99),
+        _ => panic!("sled engine opened an unsupported format"),
+    }
+
+    Ok(())
+}
+
+// Should infer the engine of directories created without metadata file
+#[test]
+fn meta_legacy_layout() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(temp_dir.path().join("log_file.txt"), "")?;
+
+    let meta = EngineMeta::read(temp_dir.path())?.expect("legacy layout not detected");
+    assert_eq!(meta.engine, "kvs");
+    assert!(SledKVEngine::open(temp_dir.path()).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use kvs::{KVEngine, KVError, KVStore, Result, SledKVEngine};
+use std::fs;
+use tempfile::TempDir;
+
+// Should read a sled store without accepting writes
+#[test]
+fn read_only_sled() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledKVEngine::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    let store = SledKVEngine::open_read_only(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.scan("key")?.len(), 2);
+    match store.set("key1".to_owned(), "value3".to_owned()) {
+        Err(KVError::ReadOnly) => {}
+        _ => panic!("read-only store accepted a set"),
+    }
+    match store.remove("key2".to_owned()) {
+        Err(KVError::ReadOnly) => {}
+        _ => panic!("read-only store accepted a remove"),
+    }
+    drop(store);
+
+    let store = SledKVEngine::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Should refuse to open a directory holding no store, without creating one
+#[test]
+fn read_only_missing_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    assert!(KVStore::open_read_only(temp_dir.path()).is_err());
+    assert!(SledKVEngine::open_read_only(temp_dir.path()).is_err());
+    assert!(!temp_dir.path().join("log_file.txt").exists());
+    assert!(!temp_dir.path().join("my_old_db").exists());
+    assert!(!temp_dir.path().join("kvs_meta.json").exists());
+    assert!(!temp_dir.path().join("kvs.lock").exists());
+
+    // a sled directory is not read as a kvs one
+    drop(SledKVEngine::open(temp_dir.path())?);
+    match KVStore::open_read_only(temp_dir.path()) {
+        Err(KVError::WrongEngine { .. }) => {}
+        _ => panic!("sled directory opened as kvs"),
+    }
+
+    Ok(())
+}
+
+// Should never append to the log of a read-only kvs store
+#[test]
+fn read_only_kvs_log_untouched() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KVStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    store.remove("key2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    drop(store);
+
+    let log_path = temp_dir.path().join("log_file.txt");
+    let len = fs::metadata(&log_path)?.len();
+    let store = KVStore::open_read_only(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert!(store.set("key1".to_owned(), "value2".to_owned()).is_err());
+    assert!(store.remove("key1".to_owned()).is_err());
+    assert_eq!(store.scan("")?.len(), 1);
+    assert_eq!(fs::metadata(&log_path)?.len(), len);
+    drop(store);
+
+    // a writer reopening the log appends after the existing entries
+    let store = KVStore::open(temp_dir.path())?;
+    store.set("key3".to_owned(), "value4".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value4".to_owned()));
+
+    Ok(())
+}
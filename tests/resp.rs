@@ -0,0 +1,375 @@
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+use kvs::{KVEngine, KVServer, MemoryKVEngine, Result, ServerHandle, ServerProtocol};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// Reply as returned by a Redis server.
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+}
+
+struct RespClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl RespClient {
+    fn connect(handle: &ServerHandle) -> Result<Self> {
+        let writer = TcpStream::connect(handle.addr())?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(RespClient { reader, writer })
+    }
+
+    fn command(&mut self, args: &[&str]) -> Result<Reply> {
+        let mut buf = format!("*{}\r\n", args.len());
+        for arg in args {
+            buf.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+        self.writer.write_all(buf.as_bytes())?;
+        self.reply()
+    }
+
+    /// Keys of every page of a `SCAN` given `options`.
+    fn scan_all(&mut self, options: &[&str]) -> Result<Vec<Reply>> {
+        let mut keys = Vec::new();
+        let mut cursor = "0".to_owned();
+        loop {
+            let mut args = vec!["SCAN", cursor.as_str()];
+            args.extend_from_slice(options);
+            match self.command(&args)? {
+                Reply::Array(mut items) => {
+                    if let Reply::Array(page) = items.pop().unwrap() {
+                        keys.extend(page);
+                    }
+                    cursor = match items.pop().unwrap() {
+                        Reply::Bulk(Some(cursor)) => cursor,
+                        other => panic!("unexpected cursor {:?}", other),
+                    };
+                }
+                other => panic!("unexpected reply {:?}", other),
+            }
+            if cursor == "0" {
+                return Ok(keys);
+            }
+        }
+    }
+
+    fn reply(&mut self) -> Result<Reply> {
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        let (kind, rest) = line.trim_end().split_at(1);
+        let reply = match kind {
+            "+" => Reply::Status(rest.to_owned()),
+            "-" => Reply::Error(rest.to_owned()),
+            ":" => Reply::Integer(rest.parse().unwrap()),
+            "$" if rest == "-1" => Reply::Bulk(None),
+            "$" => {
+                let mut buf = vec![0; rest.parse::<usize>().unwrap() + 2];
+                self.reader.read_exact(&mut buf)?;
+                buf.truncate(buf.len() - 2);
+                Reply::Bulk(Some(String::from_utf8(buf)?))
+            }
+            "*" => {
+                let len: usize = rest.parse().unwrap();
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    items.push(self.reply()?);
+                }
+                Reply::Array(items)
+            }
+            _ => panic!("unexpected reply {}", line),
+        };
+        Ok(reply)
+    }
+}
+
+fn spawn<E: KVEngine + Send + 'static>(server: KVServer<E>) -> Result<ServerHandle> {
+    common::spawn(server.protocol(ServerProtocol::Resp))
+}
+
+fn ok() -> Reply {
+    Reply::Status("OK".to_owned())
+}
+
+fn bulk(val: &str) -> Reply {
+    Reply::Bulk(Some(val.to_owned()))
+}
+
+// Should answer the basic commands of Redis clients
+#[test]
+fn resp_commands() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    let mut client = RespClient::connect(&handle)?;
+
+    assert_eq!(client.command(&["PING"])?, Reply::Status("PONG".to_owned()));
+    assert_eq!(client.command(&["ping", "hello"])?, bulk("hello"));
+
+    assert_eq!(client.command(&["SET", "key1", "value1"])?, ok());
+    assert_eq!(client.command(&["GET", "key1"])?, bulk("value1"));
+    assert_eq!(client.command(&["GET", "key2"])?, Reply::Bulk(None));
+
+    assert_eq!(client.command(&["MSET", "key2", "value2", "key3", "value3"])?, ok());
+    assert_eq!(
+        client.command(&["MGET", "key1", "key4", "key3"])?,
+        Reply::Array(vec![bulk("value1"), Reply::Bulk(None), bulk("value3")])
+    );
+    assert_eq!(client.command(&["EXISTS", "key1", "key2", "key4"])?, Reply::Integer(2));
+    assert_eq!(client.command(&["DEL", "key1", "key4"])?, Reply::Integer(1));
+    assert_eq!(client.command(&["EXISTS", "key1"])?, Reply::Integer(0));
+
+    match client.command(&["INFO"])? {
+        Reply::Bulk(Some(info)) => assert!(info.contains("connected_clients:1")),
+        other => panic!("unexpected reply {:?}", other),
+    }
+
+    // the connection stays usable after an error
+    match client.command(&["FLUSHALL"])? {
+        Reply::Error(message) => assert!(message.starts_with("ERR unknown command")),
+        other => panic!("unexpected reply {:?}", other),
+    }
+    match client.command(&["GET"])? {
+        Reply::Error(message) => assert!(message.starts_with("ERR wrong number of arguments")),
+        other => panic!("unexpected reply {:?}", other),
+    }
+    assert_eq!(client.command(&["QUIT"])?, ok());
+
+    Ok(())
+}
+
+// Should honour the NX, XX and EX options of SET
+#[test]
+fn resp_set_options() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    let mut client = RespClient::connect(&handle)?;
+
+    assert_eq!(client.command(&["SET", "key1", "value1", "XX"])?, Reply::Bulk(None));
+    assert_eq!(client.command(&["SET", "key1", "value1", "NX"])?, ok());
+    assert_eq!(client.command(&["SET", "key1", "value2", "NX"])?, Reply::Bulk(None));
+    assert_eq!(client.command(&["SET", "key1", "value2", "xx"])?, ok());
+    assert_eq!(client.command(&["GET", "key1"])?, bulk("value2"));
+
+    assert_eq!(client.command(&["SET", "key2", "value1", "PX", "100"])?, ok());
+    assert_eq!(client.command(&["SET", "key3", "value1", "EX", "3600"])?, ok());
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(client.command(&["GET", "key2"])?, Reply::Bulk(None));
+    assert_eq!(client.command(&["EXISTS", "key2"])?, Reply::Integer(0));
+    assert_eq!(client.command(&["GET", "key3"])?, bulk("value1"));
+
+    for args in &[
+        &["SET", "key1", "value1", "NX", "XX"][..],
+        &["SET", "key1", "value1", "EX", "0"][..],
+        &["SET", "key1", "value1", "EX"][..],
+        &["SET", "key1", "value1", "KEEP"][..],
+    ] {
+        match client.command(args)? {
+            Reply::Error(message) => assert!(message.starts_with("ERR")),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    Ok(())
+}
+
+// Should increment integers and refuse anything else
+#[test]
+fn resp_incr() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    let mut client = RespClient::connect(&handle)?;
+
+    assert_eq!(client.command(&["INCR", "counter"])?, Reply::Integer(1));
+    assert_eq!(client.command(&["INCR", "counter"])?, Reply::Integer(2));
+    assert_eq!(client.command(&["GET", "counter"])?, bulk("2"));
+
+    client.command(&["SET", "key1", "value1"])?;
+    assert_eq!(
+        client.command(&["INCR", "key1"])?,
+        Reply::Error("ERR value is not an integer or out of range".to_owned())
+    );
+
+    // concurrent increments are not lost
+    let clients: Vec<_> = (0..4)
+        .map(|_| {
+            let mut client = RespClient::connect(&handle).unwrap();
+            thread::spawn(move || {
+                for _ in 0..25 {
+                    client.command(&["INCR", "shared"]).unwrap();
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+    assert_eq!(client.command(&["GET", "shared"])?, bulk("100"));
+
+    Ok(())
+}
+
+// Should iterate over keys with SCAN cursors
+#[test]
+fn resp_scan() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    let mut client = RespClient::connect(&handle)?;
+
+    for i in 0..25 {
+        client.command(&["SET", &format!("user:{:02}", i), "value"])?;
+    }
+    client.command(&["SET", "other", "value"])?;
+
+    let keys = client.scan_all(&["MATCH", "user:*", "COUNT", "10"])?;
+    let expected: Vec<_> = (0..25).map(|i| bulk(&format!("user:{:02}", i))).collect();
+    assert_eq!(keys, expected);
+
+    let keys = client.scan_all(&["MATCH", "user:?5"])?;
+    assert_eq!(keys, vec![bulk("user:05"), bulk("user:15")]);
+
+    // a call looks at COUNT keys at most, even when none of them matches
+    let reply = client.command(&["SCAN", "0", "MATCH", "*none", "COUNT", "10"])?;
+    match reply {
+        Reply::Array(items) => {
+            assert_ne!(items[0], bulk("0"));
+            assert_eq!(items[1], Reply::Array(Vec::new()));
+        }
+        other => panic!("unexpected reply {:?}", other),
+    }
+
+    // keys written before the cursor neither repeat nor skip the next keys
+    let cursor = match client.command(&["SCAN", "0", "MATCH", "user:*", "COUNT", "10"])? {
+        Reply::Array(items) => match &items[0] {
+            Reply::Bulk(Some(cursor)) => cursor.to_owned(),
+            other => panic!("unexpected cursor {:?}", other),
+        },
+        other => panic!("unexpected reply {:?}", other),
+    };
+    client.command(&["SET", "user:000", "value"])?;
+    client.command(&["DEL", "user:01"])?;
+    let reply = client.command(&["SCAN", &cursor, "MATCH", "user:*", "COUNT", "2"])?;
+    match reply {
+        Reply::Array(items) => assert_eq!(
+            items[1],
+            Reply::Array(vec![bulk("user:10"), bulk("user:11")])
+        ),
+        other => panic!("unexpected reply {:?}", other),
+    }
+    match client.command(&["SCAN", "12345", "COUNT", "2"])? {
+        Reply::Error(message) => assert_eq!(message, "ERR invalid cursor"),
+        other => panic!("unexpected reply {:?}", other),
+    }
+
+    // patterns with many stars are matched in linear time
+    client.command(&["SET", &"a".repeat(100), "value"])?;
+    let reply = client.command(&["SCAN", "0", "MATCH", "a*a*a*a*a*a*a*a*a*a*a*b"])?;
+    assert_eq!(reply, Reply::Array(vec![bulk("0"), Reply::Array(Vec::new())]));
+    assert!(client.scan_all(&["MATCH", "*a\\*?a"])?.is_empty());
+
+    Ok(())
+}
+
+// Should understand inline and pipelined commands
+#[test]
+fn resp_inline_and_pipelined() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    let mut client = RespClient::connect(&handle)?;
+
+    client.writer.write_all(b"SET key1 value1\r\nGET key1\r\n")?;
+    assert_eq!(client.reply()?, ok());
+    assert_eq!(client.reply()?, bulk("value1"));
+
+    // a malformed request closes the connection
+    client.writer.write_all(b"*1\r\n+PING\r\n")?;
+    match client.reply()? {
+        Reply::Error(message) => assert!(message.starts_with("ERR Protocol error")),
+        other => panic!("unexpected reply {:?}", other),
+    }
+    let mut rest = Vec::new();
+    client.reader.read_to_end(&mut rest)?;
+    assert!(rest.is_empty());
+
+    Ok(())
+}
+
+// Should refuse clients past the limit and inline commands too long
+#[test]
+fn resp_limits() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()).max_clients(1))?;
+    let mut client = RespClient::connect(&handle)?;
+    assert_eq!(client.command(&["PING"])?, Reply::Status("PONG".to_owned()));
+
+    let mut refused = RespClient::connect(&handle)?;
+    assert_eq!(
+        refused.reply()?,
+        Reply::Error("ERR max number of clients reached".to_owned())
+    );
+
+    client.writer.write_all(&vec![b'a'; 70 * 1024])?;
+    match client.reply()? {
+        Reply::Error(message) => assert!(message.contains("too big inline request")),
+        other => panic!("unexpected reply {:?}", other),
+    }
+    drop(client);
+
+    // the connection closed, the slot is given to the next client
+    let mut client = loop {
+        let mut client = RespClient::connect(&handle)?;
+        if let Ok(Reply::Status(_)) = client.command(&["PING"]) {
+            break client;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(client.command(&["GET", "key1"])?, Reply::Bulk(None));
+
+    // declared lengths are not allocated ahead of the data
+    client.writer.write_all(b"*1048576\r\n$536870912\r\nPING")?;
+    client.writer.shutdown(Shutdown::Write)?;
+    match client.reply()? {
+        Reply::Error(message) => assert!(message.contains("unexpected end of stream")),
+        other => panic!("unexpected reply {:?}", other),
+    }
+
+    Ok(())
+}
+
+// Should reject writes on a read-only server
+#[test]
+fn resp_read_only() -> Result<()> {
+    let engine = MemoryKVEngine::new();
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    let handle = spawn(KVServer::read_only(engine))?;
+    let mut client = RespClient::connect(&handle)?;
+
+    assert_eq!(client.command(&["GET", "key1"])?, bulk("value1"));
+    for args in &[&["SET", "key1", "value2"][..], &["DEL", "key1"][..], &["INCR", "key2"][..]] {
+        match client.command(args)? {
+            Reply::Error(message) => assert!(message.starts_with("READONLY")),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    Ok(())
+}
+
+// Should close idle connections when shutting down
+#[test]
+fn resp_shutdown() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    let mut client = RespClient::connect(&handle)?;
+    assert_eq!(client.command(&["SET", "key1", "value1"])?, ok());
+
+    handle.stop()?;
+    let mut rest = Vec::new();
+    client.reader.read_to_end(&mut rest)?;
+    assert!(rest.is_empty());
+
+    Ok(())
+}
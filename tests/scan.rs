@@ -0,0 +1,86 @@
+use kvs::{
+    BTreeKVEngine, KVEngine, KVError, KVPair, KVPrefix, KVStore, LsmKVEngine, MemoryKVEngine,
+    Result, SledKVEngine,
+};
+use tempfile::TempDir;
+
+fn check_scan<E: KVEngine>(store: E) -> Result<()> {
+    store.set("b1".to_owned(), "value1".to_owned())?;
+    store.set("a1".to_owned(), "value0".to_owned())?;
+    store.set("b2".to_owned(), "value2".to_owned())?;
+    store.set("b3".to_owned(), "value3".to_owned())?;
+    store.set("c1".to_owned(), "value4".to_owned())?;
+    store.set("b1".to_owned(), "value5".to_owned())?;
+    store.remove("b2".to_owned())?;
+
+    let pairs: Vec<(String, String)> = store
+        .scan("b")?
+        .into_iter()
+        .map(|KVPair { key, val }| (key, val))
+        .collect();
+    assert_eq!(
+        pairs,
+        vec![
+            ("b1".to_owned(), "value5".to_owned()),
+            ("b3".to_owned(), "value3".to_owned())
+        ]
+    );
+    assert_eq!(store.scan("")?.len(), 4);
+    assert!(store.scan("d")?.is_empty());
+
+    // pages resume after the last key read
+    let keys = |pairs: Vec<KVPair>| pairs.into_iter().map(|pair| pair.key).collect::<Vec<_>>();
+    assert_eq!(keys(store.scan_from("", None, 2)?), vec!["a1", "b1"]);
+    assert_eq!(keys(store.scan_from("", Some("b1"), 2)?), vec!["b3", "c1"]);
+    assert_eq!(keys(store.scan_from("b", Some("a"), 10)?), vec!["b1", "b3"]);
+    assert!(store.scan_from("b", Some("b3"), 10)?.is_empty());
+
+    Ok(())
+}
+
+// Engine written before scans existed
+#[derive(Clone)]
+struct NoScan;
+
+impl KVEngine for NoScan {
+    fn set(&self, _: String, _: String) -> Result<()> {
+        Ok(())
+    }
+
+    fn get(&self, _: String) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn remove(&self, _: String) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Should return live pairs matching the prefix, in key order
+#[test]
+fn scan_every_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_scan(KVStore::open(&temp_dir.path().join("kvs"))?)?;
+    check_scan(SledKVEngine::open(&temp_dir.path().join("sled"))?)?;
+    check_scan(LsmKVEngine::open(&temp_dir.path().join("lsm"))?)?;
+    check_scan(BTreeKVEngine::open(&temp_dir.path().join("btree"))?)?;
+    check_scan(MemoryKVEngine::new())?;
+    check_scan(KVPrefix::new(MemoryKVEngine::new(), "app:".to_owned()))?;
+    let sled = SledKVEngine::open(&temp_dir.path().join("sled-prefix"))?;
+    check_scan(KVPrefix::new(sled, "app:".to_owned()))?;
+
+    Ok(())
+}
+
+// Should report scans as unsupported by engines which do not implement them
+#[test]
+fn scan_unsupported() {
+    match NoScan.scan("") {
+        Err(KVError::Unsupported(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    match NoScan.scan_from("", None, 10) {
+        Err(KVError::Unsupported(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+}
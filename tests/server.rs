@@ -0,0 +1,451 @@
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+use common::{logger, spawn, wait_for};
+use kvs::{
+    Capability, Codec, ErrorCode, KVClient, KVEngine, KVError, KVRequest, KVResponse, KVServer,
+    MemoryKVEngine, Result, ServerHandle, SledKVEngine, MAX_FRAME_LEN, PROTOCOL_VERSION,
+};
+use serde::Deserialize;
+use serde_json::{Deserializer, Value};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn set(handle: &ServerHandle, key: &str, val: &str) -> Result<()> {
+    let req = KVRequest::Set {
+        key: key.to_owned(),
+        val: val.to_owned(),
+    };
+    KVClient::new(handle.addr().to_string(), req)?.connect()?;
+    Ok(())
+}
+
+fn get(handle: &ServerHandle, key: &str) -> Result<Option<String>> {
+    let req = KVRequest::Get {
+        key: key.to_owned(),
+    };
+    KVClient::new(handle.addr().to_string(), req)?.connect()
+}
+
+// Sends raw `bytes` to the server and reads its only response.
+fn exchange(handle: &ServerHandle, bytes: &[u8]) -> Result<KVResponse> {
+    let mut stream = TcpStream::connect(handle.addr())?;
+    stream.write_all(bytes)?;
+    let mut deserializer = Deserializer::from_reader(&stream);
+    Ok(KVResponse::deserialize(&mut deserializer)?)
+}
+
+// Should bind an ephemeral port and serve requests right away
+#[test]
+fn server_spawn() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    assert_ne!(handle.addr().port(), 0);
+
+    set(&handle, "key1", "value1")?;
+    assert_eq!(get(&handle, "key1")?, Some("value1".to_owned()));
+
+    // several servers run side by side in one process
+    let other = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    assert_ne!(other.addr(), handle.addr());
+    set(&other, "key1", "value2")?;
+    assert_eq!(get(&handle, "key1")?, Some("value1".to_owned()));
+
+    let addr = handle.addr();
+    handle.stop()?;
+    other.stop()?;
+    assert!(TcpStream::connect(addr).is_err());
+
+    Ok(())
+}
+
+// Should tell a missing key from any stored value
+#[test]
+fn server_get_missing_key() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    assert_eq!(get(&handle, "key1")?, None);
+
+    set(&handle, "key1", "Key not found")?;
+    assert_eq!(get(&handle, "key1")?, Some("Key not found".to_owned()));
+
+    Ok(())
+}
+
+// Should stop serving, release the store and keep every acknowledged write
+#[test]
+fn server_shutdown() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let handle = spawn(KVServer::new(SledKVEngine::open(temp_dir.path())?).threads(4))?;
+
+    for i in 0..10 {
+        set(&handle, &format!("key{}", i), &format!("value{}", i))?;
+    }
+    let addr = handle.addr();
+    handle.stop()?;
+    assert!(TcpStream::connect(addr).is_err());
+
+    let store = SledKVEngine::open(temp_dir.path())?;
+    for i in 0..10 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+
+    Ok(())
+}
+
+// Should stop the server from a shutdown handle, as a signal handler does
+#[test]
+fn server_shutdown_handle() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()).threads(2))?;
+    let addr = handle.addr();
+
+    handle.shutdown_handle().shutdown();
+    handle.join()?;
+    assert!(TcpStream::connect(addr).is_err());
+
+    Ok(())
+}
+
+// Should dump the memory engine when shutting down
+#[test]
+fn server_shutdown_flushes_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let interval = Duration::from_secs(3600);
+    let engine = MemoryKVEngine::open_with_dump(temp_dir.path(), interval)?;
+    let handle = spawn(KVServer::new(engine))?;
+
+    set(&handle, "key1", "value1")?;
+    drop(handle);
+
+    let store = MemoryKVEngine::open_with_dump(temp_dir.path(), interval)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+// Should report the kind of every failed request
+#[test]
+fn server_error_codes() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    let req = KVRequest::Rm {
+        key: "key1".to_owned(),
+    };
+    match KVClient::new(handle.addr().to_string(), req)?.connect() {
+        Err(KVError::NotFound(_)) => {}
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    // a request that is not JSON at all
+    let mut stream = TcpStream::connect(handle.addr())?;
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n")?;
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf)?;
+    match serde_json::from_slice(&buf)? {
+        KVResponse::Err { code, .. } => assert_eq!(code, ErrorCode::Protocol),
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    let engine = MemoryKVEngine::new();
+    let read_only = spawn(KVServer::read_only(engine))?;
+    let req = KVRequest::Set {
+        key: "key1".to_owned(),
+        val: "value1".to_owned(),
+    };
+    match KVClient::new(read_only.addr().to_string(), req)?.connect() {
+        Err(KVError::ReadOnly) => {}
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    Ok(())
+}
+
+// Should map every error code back to the error it stands for
+#[test]
+fn server_error_code_mapping() {
+    let errors = vec![
+        KVError::FailGet("key1".to_owned()),
+        KVError::WrongType("not an integer".to_owned()),
+        KVError::PreconditionFailed("key exists".to_owned()),
+        KVError::ReadOnly,
+        KVError::InvalidKey("too long".to_owned()),
+        KVError::Protocol("bad request".to_owned()),
+        KVError::DirectoryLocked("/tmp".to_owned()),
+    ];
+    for error in errors {
+        let code = error.code();
+        let remote = KVError::from_response(code, error.to_string());
+        assert_eq!(remote.code(), code);
+    }
+
+    // codes of a newer server are reported as plain errors
+    let response = r#"{"Err": {"code": "Throttled", "message": "slow down"}}"#;
+    match serde_json::from_str(response).unwrap() {
+        KVResponse::Err { code, message } => {
+            assert_eq!(code, ErrorCode::Unknown);
+            match KVError::from_response(code, message) {
+                KVError::StringError(message) => assert_eq!(message, "slow down"),
+                other => panic!("unexpected error {:?}", other),
+            }
+        }
+        other => panic!("unexpected response {:?}", other),
+    }
+}
+
+// Should agree on the protocol version and capabilities before a request
+#[test]
+fn server_handshake() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    set(&handle, "key1", "value1")?;
+
+    let req = KVRequest::Get {
+        key: "key1".to_owned(),
+    };
+    let mut client = KVClient::new(handle.addr().to_string(), req)?;
+    assert_eq!(client.connect()?, Some("value1".to_owned()));
+    assert_eq!(client.protocol_version(), PROTOCOL_VERSION);
+    assert_eq!(client.capabilities(), &[Capability::Scans]);
+
+    // versions from the future are negotiated down, unknown capabilities dropped
+    let hello = KVRequest::Hello {
+        version: PROTOCOL_VERSION + 1,
+        capabilities: vec![Capability::Scans, Capability::Compression],
+        codecs: Vec::new(),
+    };
+    match exchange(&handle, &serde_json::to_vec(&hello)?)? {
+        KVResponse::Hello {
+            version,
+            capabilities,
+            codec,
+        } => {
+            assert_eq!(version, PROTOCOL_VERSION);
+            assert_eq!(codec, Codec::Json);
+            assert_eq!(capabilities, vec![Capability::Scans]);
+        }
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    // versions older than the server supports are refused
+    let hello = KVRequest::Hello {
+        version: 0,
+        capabilities: Vec::new(),
+        codecs: Vec::new(),
+    };
+    match exchange(&handle, &serde_json::to_vec(&hello)?)? {
+        KVResponse::Err { code, .. } => assert_eq!(code, ErrorCode::UnsupportedVersion),
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    Ok(())
+}
+
+// Should speak every codec once negotiated
+#[test]
+fn server_codecs() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    let addr = handle.addr().to_string();
+
+    for &codec in &[Codec::Json, Codec::MessagePack, Codec::Bincode] {
+        let req = KVRequest::Set {
+            key: format!("{:?}", codec),
+            val: "value1".to_owned(),
+        };
+        let mut client = KVClient::new(addr.to_owned(), req)?.prefer_codec(codec);
+        client.connect()?;
+        assert_eq!(client.codec(), codec);
+
+        let req = KVRequest::Get {
+            key: format!("{:?}", codec),
+        };
+        let mut client = KVClient::new(addr.to_owned(), req)?.prefer_codec(codec);
+        assert_eq!(client.connect()?, Some("value1".to_owned()));
+
+        let req = KVRequest::Rm {
+            key: "key1".to_owned(),
+        };
+        match KVClient::new(addr.to_owned(), req)?.prefer_codec(codec).connect() {
+            Err(KVError::NotFound(_)) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    // codecs unknown to the server are skipped
+    let hello = br#"{"Hello":{"version":1,"capabilities":[],"codecs":["Zstd","Bincode"]}}"#;
+    match exchange(&handle, hello)? {
+        KVResponse::Hello { codec, .. } => assert_eq!(codec, Codec::Bincode),
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    Ok(())
+}
+
+// Should refuse binary messages longer than the frame limit, without
+// allocating them
+#[test]
+fn codec_frame_too_large() -> Result<()> {
+    for &codec in &[Codec::MessagePack, Codec::Bincode] {
+        let mut frame = (MAX_FRAME_LEN + 1).to_be_bytes().to_vec();
+        frame.extend_from_slice(b"short");
+        match codec.read::<KVRequest, _>(&frame[..]) {
+            Err(KVError::Protocol(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let frame = u32::MAX.to_be_bytes();
+        assert!(codec.read::<KVRequest, _>(&frame[..]).is_err());
+    }
+
+    Ok(())
+}
+
+// Should keep serving clients which predate the handshake
+#[test]
+fn server_handshake_legacy_client() -> Result<()> {
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()))?;
+    set(&handle, "key1", "value1")?;
+
+    let req = KVRequest::Get {
+        key: "key1".to_owned(),
+    };
+    match exchange(&handle, &serde_json::to_vec(&req)?)? {
+        KVResponse::Ok(val) => assert_eq!(val, Some("value1".to_owned())),
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    Ok(())
+}
+
+// Should fall back to bare requests against servers which predate the handshake
+#[test]
+fn server_handshake_legacy_server() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let server = thread::spawn(move || -> Result<Vec<Value>> {
+        let answers = [r#"{"Err":"unknown variant `Hello`"}"#, r#"{"Ok":"value1"}"#];
+        let mut requests = Vec::new();
+        for answer in answers.iter() {
+            let (mut stream, _) = listener.accept()?;
+            let mut deserializer = Deserializer::from_reader(&stream);
+            requests.push(Value::deserialize(&mut deserializer)?);
+            stream.write_all(answer.as_bytes())?;
+        }
+        Ok(requests)
+    });
+
+    let req = KVRequest::Get {
+        key: "key1".to_owned(),
+    };
+    let mut client = KVClient::new(addr.to_string(), req)?;
+    assert_eq!(client.connect()?, Some("value1".to_owned()));
+    assert_eq!(client.protocol_version(), 0);
+    assert!(client.capabilities().is_empty());
+
+    let requests = server.join().expect("legacy server panicked")?;
+    assert!(requests[0].get("Hello").is_some());
+    assert_eq!(requests[1], serde_json::json!({"Get": {"key": "key1"}}));
+
+    Ok(())
+}
+
+// Should refuse servers answering the handshake with a version it does not speak
+#[test]
+fn server_handshake_newer_version() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let server = thread::spawn(move || -> Result<()> {
+        let (mut stream, _) = listener.accept()?;
+        let mut deserializer = Deserializer::from_reader(&stream);
+        Value::deserialize(&mut deserializer)?;
+        let hello = KVResponse::Hello {
+            version: PROTOCOL_VERSION + 1,
+            capabilities: Vec::new(),
+            codec: Codec::Json,
+        };
+        stream.write_all(&serde_json::to_vec(&hello)?)?;
+        Ok(())
+    });
+
+    let req = KVRequest::Get {
+        key: "key1".to_owned(),
+    };
+    match KVClient::new(addr.to_string(), req)?.connect() {
+        Err(KVError::UnsupportedProtocol(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    server.join().expect("fake server panicked")
+}
+
+// Should serve a Unix domain socket, replacing a stale one and removing it
+// on shutdown
+#[cfg(unix)]
+#[test]
+fn server_unix_socket() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+
+    let temp_dir = TempDir::new()?;
+    let socket = temp_dir.path().join("kvs.sock");
+    let addr = format!("unix:{}", socket.display());
+    // left behind by a server that died
+    drop(UnixListener::bind(&socket)?);
+
+    let mut server = KVServer::new(MemoryKVEngine::new()).socket_mode(0o600);
+    let shutdown = server.shutdown_handle();
+    let server_addr = addr.to_owned();
+    let thread = thread::spawn(move || server.run(server_addr, &logger()));
+    // the stale socket is gone once the server listens
+    wait_for(&addr);
+    assert_eq!(socket.metadata()?.permissions().mode() & 0o777, 0o600);
+
+    let req = KVRequest::Set {
+        key: "key1".to_owned(),
+        val: "value1".to_owned(),
+    };
+    KVClient::new(addr.to_owned(), req)?.connect()?;
+    let req = KVRequest::Get {
+        key: "key1".to_owned(),
+    };
+    assert_eq!(KVClient::new(addr.to_owned(), req)?.connect()?, Some("value1".to_owned()));
+
+    // a second server does not take over the live socket
+    let mut other = KVServer::new(MemoryKVEngine::new());
+    assert!(other.run(addr.to_owned(), &logger()).is_err());
+
+    shutdown.shutdown();
+    thread.join().unwrap()?;
+    assert!(!socket.exists());
+
+    Ok(())
+}
+
+// Should listen on every address of a list
+#[cfg(unix)]
+#[test]
+fn server_multiple_addrs() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let socket = format!("unix:{}", temp_dir.path().join("kvs.sock").display());
+    let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let tcp = format!("127.0.0.1:{}", port);
+
+    let mut server = KVServer::new(MemoryKVEngine::new());
+    let shutdown = server.shutdown_handle();
+    let addrs = format!("{},{}", tcp, socket);
+    let thread = thread::spawn(move || server.run(addrs, &logger()));
+    wait_for(&socket);
+
+    let req = KVRequest::Set {
+        key: "key1".to_owned(),
+        val: "value1".to_owned(),
+    };
+    KVClient::new(tcp, req)?.connect()?;
+    let req = KVRequest::Get {
+        key: "key1".to_owned(),
+    };
+    assert_eq!(KVClient::new(socket, req)?.connect()?, Some("value1".to_owned()));
+
+    shutdown.shutdown();
+    thread.join().unwrap()
+}
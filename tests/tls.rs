@@ -0,0 +1,126 @@
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+use common::spawn;
+use kvs::{
+    ClientTls, KVClient, KVRequest, KVServer, MemoryKVEngine, Result, ServerHandle, ServerTls,
+};
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+// Self-signed certificate, acting as its own CA, written as PEM files.
+struct Identity {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+fn identity(dir: &TempDir, name: &str) -> Result<Identity> {
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let cert = dir.path().join(format!("{}.pem", name));
+    let key = dir.path().join(format!("{}-key.pem", name));
+    fs::write(&cert, generated.serialize_pem().unwrap())?;
+    fs::write(&key, generated.serialize_private_key_pem())?;
+    Ok(Identity { cert, key })
+}
+
+// `localhost`, the name certificates are issued for
+fn localhost(handle: &ServerHandle) -> String {
+    format!("localhost:{}", handle.addr().port())
+}
+
+fn set(addr: String, tls: ClientTls) -> Result<Option<String>> {
+    let req = KVRequest::Set {
+        key: "key1".to_owned(),
+        val: "value1".to_owned(),
+    };
+    KVClient::with_tls(addr, tls, req)?.connect()
+}
+
+fn get(addr: String, tls: ClientTls) -> Result<Option<String>> {
+    let req = KVRequest::Get {
+        key: "key1".to_owned(),
+    };
+    KVClient::with_tls(addr, tls, req)?.connect()
+}
+
+// Should serve clients trusting the server certificate only
+#[test]
+fn tls_client_server() -> Result<()> {
+    let dir = TempDir::new()?;
+    let server = identity(&dir, "server")?;
+    let other = identity(&dir, "other")?;
+    let tls = ServerTls::from_pem(&server.cert, &server.key)?;
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()).tls(tls))?;
+
+    let trusted = ClientTls::from_ca(&server.cert)?;
+    set(localhost(&handle), trusted.clone())?;
+    assert_eq!(get(localhost(&handle), trusted.clone())?, Some("value1".to_owned()));
+
+    assert!(get(localhost(&handle), ClientTls::from_ca(&other.cert)?).is_err());
+    // the certificate is checked against a host name, which IPs are not
+    assert!(get(handle.addr().to_string(), trusted).is_err());
+
+    // nor do plaintext clients get an answer
+    let req = KVRequest::Get {
+        key: "key1".to_owned(),
+    };
+    assert!(KVClient::new(localhost(&handle), req)?.connect().is_err());
+
+    handle.stop()
+}
+
+// Should skip every check for insecure clients
+#[test]
+fn tls_insecure() -> Result<()> {
+    let dir = TempDir::new()?;
+    let server = identity(&dir, "server")?;
+    let tls = ServerTls::from_pem(&server.cert, &server.key)?;
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()).tls(tls))?;
+
+    set(handle.addr().to_string(), ClientTls::insecure())?;
+    assert_eq!(get(localhost(&handle), ClientTls::insecure())?, Some("value1".to_owned()));
+
+    handle.stop()
+}
+
+// Should only serve clients presenting a trusted certificate
+#[test]
+fn tls_client_certs() -> Result<()> {
+    let dir = TempDir::new()?;
+    let server = identity(&dir, "server")?;
+    let client = identity(&dir, "client")?;
+    let other = identity(&dir, "other")?;
+    let tls = ServerTls::with_client_auth(&server.cert, &server.key, &client.cert)?;
+    let handle = spawn(KVServer::new(MemoryKVEngine::new()).tls(tls))?;
+
+    let trusted = ClientTls::from_ca(&server.cert)?;
+    set(localhost(&handle), trusted.clone().identity(&client.cert, &client.key)?)?;
+    let tls = trusted.clone().identity(&client.cert, &client.key)?;
+    assert_eq!(get(localhost(&handle), tls)?, Some("value1".to_owned()));
+
+    assert!(get(localhost(&handle), trusted.clone()).is_err());
+    let tls = trusted.identity(&other.cert, &other.key)?;
+    assert!(get(localhost(&handle), tls).is_err());
+
+    handle.stop()
+}
+
+// Should report unusable PEM files
+#[test]
+fn tls_invalid_files() -> Result<()> {
+    let dir = TempDir::new()?;
+    let server = identity(&dir, "server")?;
+    let empty = dir.path().join("empty.pem");
+    fs::write(&empty, "")?;
+
+    assert!(ServerTls::from_pem(&empty, &server.key).is_err());
+    assert!(ServerTls::from_pem(&server.cert, &empty).is_err());
+    assert!(ServerTls::from_pem(&server.cert, &dir.path().join("missing.pem")).is_err());
+    assert!(ClientTls::from_ca(&empty).is_err());
+    assert!(ClientTls::insecure().identity(&server.cert, &empty).is_err());
+
+    Ok(())
+}